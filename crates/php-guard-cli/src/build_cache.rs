@@ -0,0 +1,153 @@
+//! 增量加密缓存。
+//!
+//! 以输出目录分组记录每个输出文件对应的源文件哈希、输出哈希、密钥标识和加密选项，
+//! 四者都未变化且输出文件完好时跳过重新加密。
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+pub const CACHE_PATH: &str = ".php-guard/cache/encrypt.json";
+
+const CACHE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub source: String,
+    pub source_sha256: String,
+    pub output_sha256: String,
+    pub key_id: String,
+    pub options: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuildCache {
+    version: u32,
+    /// 输出目录 -> (相对路径 -> 记录)
+    outputs: BTreeMap<String, BTreeMap<String, CacheEntry>>,
+}
+
+impl Default for BuildCache {
+    fn default() -> Self {
+        BuildCache {
+            version: CACHE_VERSION,
+            outputs: BTreeMap::new(),
+        }
+    }
+}
+
+impl BuildCache {
+    /// 缓存不存在、损坏或版本不符时返回空缓存，相当于全量构建
+    pub fn load(path: &Path) -> BuildCache {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<BuildCache>(&content).ok())
+            .filter(|cache| cache.version == CACHE_VERSION)
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    pub fn get(&self, root: &str, relative: &str) -> Option<&CacheEntry> {
+        self.outputs.get(root)?.get(relative)
+    }
+
+    pub fn insert(&mut self, root: &str, relative: &str, entry: CacheEntry) {
+        self.outputs
+            .entry(root.to_string())
+            .or_default()
+            .insert(relative.to_string(), entry);
+    }
+
+    /// 移除本次构建没有处理到的记录 (源文件已删除或被新加入的排除规则排除)，
+    /// 返回它们的相对路径，由调用方删除对应输出
+    pub fn remove_unvisited(&mut self, root: &str, visited: &BTreeSet<String>) -> Vec<String> {
+        let Some(entries) = self.outputs.get_mut(root) else {
+            return Vec::new();
+        };
+        let stale: Vec<String> = entries
+            .keys()
+            .filter(|relative| !visited.contains(*relative))
+            .cloned()
+            .collect();
+        for relative in &stale {
+            entries.remove(relative);
+        }
+        stale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(source: &str) -> CacheEntry {
+        CacheEntry {
+            source: source.to_string(),
+            source_sha256: "a".to_string(),
+            output_sha256: "b".to_string(),
+            key_id: "c".to_string(),
+            options: "d".to_string(),
+        }
+    }
+
+    fn visited(relatives: &[&str]) -> BTreeSet<String> {
+        relatives.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn test_roundtrip_and_orphans() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("a.php");
+        fs::write(&source, b"<?php").unwrap();
+
+        let mut cache = BuildCache::default();
+        cache.insert("dist", "a.php", entry(&source.to_string_lossy()));
+        cache.insert("dist", "gone.php", entry("/nonexistent/gone.php"));
+
+        let path = dir.path().join("cache/encrypt.json");
+        cache.save(&path).unwrap();
+        let mut cache = BuildCache::load(&path);
+        assert!(cache.get("dist", "a.php").is_some());
+
+        assert_eq!(
+            cache.remove_unvisited("dist", &visited(&["a.php"])),
+            ["gone.php"]
+        );
+        assert!(cache.get("dist", "gone.php").is_none());
+        assert!(cache.remove_unvisited("other", &visited(&[])).is_empty());
+
+        fs::write(&path, b"not json").unwrap();
+        assert!(BuildCache::load(&path).get("dist", "a.php").is_none());
+    }
+
+    #[test]
+    fn test_newly_excluded_source_is_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = BuildCache::default();
+        for name in ["a.php", "vendor/b.php"] {
+            let source = dir.path().join(name.replace('/', "_"));
+            fs::write(&source, b"<?php").unwrap();
+            cache.insert("dist", name, entry(&source.to_string_lossy()));
+        }
+        cache.insert("other", "vendor/b.php", entry("/src/vendor/b.php"));
+
+        // 源文件仍然存在，但本次构建排除了 vendor/
+        assert_eq!(
+            cache.remove_unvisited("dist", &visited(&["a.php"])),
+            ["vendor/b.php"]
+        );
+        assert!(cache.get("dist", "a.php").is_some());
+        assert!(cache.get("dist", "vendor/b.php").is_none());
+        assert!(cache.get("other", "vendor/b.php").is_some());
+    }
+}
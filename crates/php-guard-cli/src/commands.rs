@@ -1,82 +1,598 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use clap::Args;
 use colored::Colorize;
+use std::collections::{BTreeSet, HashMap};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-use php_guard_core::{encrypt_content, is_encrypted, read_and_decrypt_file};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 
-pub fn encrypt(paths: &[String], output_dir: Option<&str>) -> Result<()> {
+use crate::build_cache::{BuildCache, CACHE_PATH, CacheEntry};
+use crate::filter::SourceFilter;
+use crate::lint::LintOptions;
+use crate::manifest::{AuditIssue, Manifest, manifest_path};
+use crate::markers::Markers;
+use crate::preprocess::PreprocessOptions;
+
+use php_guard_core::crypto::{bytes_to_hex, key_id_bytes, sha256_hex};
+use php_guard_core::fingerprint::{self, FingerprintSource};
+use php_guard_core::format::{algorithm_name, flag_names};
+use php_guard_core::signing::{self, SIGNATURE_LENGTH, SigningKey};
+use php_guard_core::{
+    Algorithm, Compression, Date, Decryptor, Encryptor, FileClass, License, LicenseStatus,
+    classify_verified, is_encrypted, key_id,
+};
+
+fn load_signing_key(path: &str) -> Result<SigningKey> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("无法读取签名私钥: {}: {}", path, e))?;
+    signing::signing_key_from_hex(&content)
+        .map_err(|e| anyhow::anyhow!("签名私钥无效: {}: {}", path, e))
+}
+
+/// 加密参数，`encrypt` 与 `watch` 共用
+#[derive(Debug, Clone, Args)]
+pub struct BuildOptions {
+    #[arg(long, help = "Ed25519 signing key file created by `keygen`")]
+    pub sign_key: Option<String>,
+    #[arg(long, help = "Skip files or directories matching this pattern")]
+    pub exclude: Vec<String>,
+    #[command(flatten)]
+    pub preprocess: PreprocessOptions,
+    #[arg(
+        long,
+        default_value = "xor",
+        help = "Cipher: xor (legacy-compatible) or chacha20 (random per-file nonce, faster to decrypt)"
+    )]
+    pub algorithm: Algorithm,
+    #[arg(
+        long = "compress",
+        value_name = "COMPRESS",
+        default_value = "none",
+        help = "Compress sources before encrypting: none, deflate or zstd"
+    )]
+    pub compression: Compression,
+    /// 分块加密的块大小，`None` 为整体加密
+    #[arg(
+        long,
+        help = "Encrypt in independently authenticated chunks of this many bytes (requires --algorithm chacha20)"
+    )]
+    pub chunk_size: Option<u32>,
+    #[command(flatten)]
+    pub lint: LintOptions,
+    #[arg(long, help = "Only encrypt files marked with @php-guard-encrypt")]
+    pub marked_only: bool,
+}
+
+pub struct EncryptOptions {
+    pub output: Option<String>,
+    pub manifest: Option<String>,
+    /// 忽略构建缓存，重新加密全部文件
+    pub force: bool,
+    pub build: BuildOptions,
+}
+
+/// 展开命令行参数，返回 (源文件, 相对输出根目录的路径)。
+///
+/// 目录参数保留其内部的目录结构并应用筛选规则，文件参数只取文件名。
+fn collect_sources(paths: &[String], filter: &SourceFilter) -> Vec<(PathBuf, PathBuf)> {
+    let mut sources = Vec::new();
+    for path in paths {
+        let path_obj = Path::new(path);
+        if path_obj.is_file() {
+            let relative = PathBuf::from(path_obj.file_name().unwrap());
+            sources.push((path_obj.to_path_buf(), relative));
+        } else if path_obj.is_dir() {
+            for file in filter.walk(path_obj) {
+                let relative = file.strip_prefix(path_obj).unwrap().to_path_buf();
+                sources.push((file, relative));
+            }
+        }
+    }
+    sources
+}
+
+pub fn encrypt(paths: &[String], options: &EncryptOptions) -> Result<()> {
     println!("{}", "PHP-Guard 文件加密".green().bold());
     println!("{}", "=".repeat(40));
 
+    let filter = SourceFilter::new(&options.build.exclude);
+    let sources = collect_sources(paths, &filter);
+
+    // 先检查全部文件，任何一个失败都不写出结果
+    if options.build.lint.lint {
+        let linter = options.build.lint.linter()?;
+        let mut errors = 0;
+        for (source, _) in &sources {
+            if let Some(error) = linter.check(source)? {
+                eprintln!(
+                    "{} {}失败: {}:{}",
+                    "✗".red(),
+                    linter.name(),
+                    source.display(),
+                    error
+                );
+                errors += 1;
+            }
+        }
+        if errors > 0 {
+            anyhow::bail!("{}失败: {} 个文件，已中止加密", linter.name(), errors);
+        }
+        println!(
+            "{} {}通过: {} 个文件",
+            "✓".green(),
+            linter.name(),
+            sources.len()
+        );
+    }
+
+    let pipeline = Pipeline::new(&options.build)?;
+    let mut manifest = options.manifest.as_ref().map(|_| Manifest::new());
+
+    // 缓存只用于 --output：原地加密后源文件已被覆盖，无从比较
+    let mut cache = match options.output.as_deref() {
+        Some(dir) => {
+            fs::create_dir_all(dir)?;
+            let root = fs::canonicalize(dir)?.to_string_lossy().into_owned();
+            // --force 只重建本输出目录：不查找记录，本次处理的文件重新写入记录，
+            // 其余记录在构建结束时连同输出一起移除；其他输出目录的记录保持不变
+            Some((root, BuildCache::load(Path::new(CACHE_PATH))))
+        }
+        None => None,
+    };
+    let cache_options = pipeline.cache_key();
+
     let mut total = 0;
     let mut skipped = 0;
+    let mut plain = 0;
+    let mut unchanged = 0;
+    let mut sizes = SizeReport::default();
+    let mut visited = BTreeSet::new();
 
-    for path in paths {
-        let path_obj = Path::new(path);
-        if path_obj.is_file() {
-            match encrypt_single_file(&path_obj, output_dir)? {
-                true => total += 1,
-                false => skipped += 1,
+    for (source, relative) in sources {
+        let output_path = match options.output.as_deref() {
+            Some(dir) => Path::new(dir).join(&relative),
+            None => source.clone(),
+        };
+
+        if let Some((root, cache)) = cache.as_mut() {
+            let cache_key = manifest_path(&relative);
+            visited.insert(cache_key.clone());
+            let plaintext = fs::read(&source)?;
+            let source_sha256 = sha256_hex(&plaintext);
+
+            let entry = match options.force {
+                true => None,
+                false => cache.get(root, &cache_key),
+            };
+            if let Some(ciphertext) = entry.and_then(|entry| {
+                cached_output(
+                    entry,
+                    &source_sha256,
+                    &pipeline.key_id(),
+                    &cache_options,
+                    &output_path,
+                )
+            }) {
+                unchanged += 1;
+                sizes.add(&pipeline, &plaintext, &ciphertext);
+                if let Some(manifest) = manifest.as_mut() {
+                    manifest.add(&relative, &plaintext, &ciphertext);
+                }
+                continue;
             }
-        } else if path_obj.is_dir() {
-            for entry in walkdir::WalkDir::new(path_obj)
-                .into_iter()
-                .filter_map(|e| e.ok())
-                .filter(|e| {
-                    e.path()
-                        .extension()
-                        .map(|ext| ext == "php")
-                        .unwrap_or(false)
-                })
-            {
-                match encrypt_single_file(entry.path(), output_dir)? {
+
+            if let Some(processed) = process_single_file(&source, &output_path, &pipeline)? {
+                match processed.encrypted {
+                    true => total += 1,
+                    false => plain += 1,
+                }
+                sizes.add(&pipeline, &processed.plaintext, &processed.output);
+                let entry = CacheEntry {
+                    source: fs::canonicalize(&source)?.to_string_lossy().into_owned(),
+                    source_sha256,
+                    output_sha256: sha256_hex(&processed.output),
+                    key_id: pipeline.key_id(),
+                    options: cache_options.clone(),
+                };
+                cache.insert(root, &cache_key, entry);
+                if let Some(manifest) = manifest.as_mut() {
+                    manifest.add(&relative, &processed.plaintext, &processed.output);
+                }
+            } else {
+                skipped += 1;
+            }
+            continue;
+        }
+
+        match process_single_file(&source, &output_path, &pipeline)? {
+            Some(processed) => {
+                match processed.encrypted {
                     true => total += 1,
-                    false => skipped += 1,
+                    false => plain += 1,
                 }
+                sizes.add(&pipeline, &processed.plaintext, &processed.output);
+                if let Some(manifest) = manifest.as_mut() {
+                    manifest.add(&relative, &processed.plaintext, &processed.output);
+                }
+            }
+            None => skipped += 1,
+        }
+    }
+
+    let mut removed = 0;
+    if let (Some((root, mut cache)), Some(dir)) = (cache, options.output.as_deref()) {
+        for relative in cache.remove_unvisited(&root, &visited) {
+            let output_path = Path::new(dir).join(&relative);
+            if output_path.is_file() {
+                fs::remove_file(&output_path)?;
+                println!(
+                    "{} 源文件已删除或已排除，移除输出: {}",
+                    "-".yellow(),
+                    output_path.display()
+                );
+                removed += 1;
             }
         }
+        cache.save(Path::new(CACHE_PATH))?;
     }
 
     println!("\n{} 加密完成: {} 个文件", "✓".green(), total);
+    if unchanged > 0 {
+        println!("{} 未变更: {} 个文件", "-".yellow(), unchanged);
+    }
+    if plain > 0 {
+        println!("{} 保留明文: {} 个文件", "-".yellow(), plain);
+    }
     if skipped > 0 {
         println!("{} 跳过: {} 个文件 (已加密)", "-".yellow(), skipped);
     }
+    if removed > 0 {
+        println!("{} 已移除: {} 个文件", "-".yellow(), removed);
+    }
+    if pipeline.encryptor.compression() != Compression::None && sizes.source > 0 {
+        println!(
+            "{} 压缩 ({}): {} → {} 字节，减少 {:.1}%",
+            "✓".green(),
+            pipeline.encryptor.compression().name(),
+            sizes.source,
+            sizes.output,
+            sizes.saved_percent()
+        );
+    }
+
+    if let (Some(manifest), Some(path)) = (manifest, options.manifest.as_deref()) {
+        manifest.save(Path::new(path))?;
+        println!("{} 清单已生成: {}", "✓".green(), path);
+    }
 
     Ok(())
 }
 
-fn encrypt_single_file(path: &Path, output_dir: Option<&str>) -> Result<bool> {
+/// 预处理、加密和签名，`encrypt` 与 `watch` 共用
+struct Pipeline {
+    encryptor: Encryptor,
+    /// 预处理闭包无法比较，缓存键使用原始选项
+    preprocess: PreprocessOptions,
+    /// 只加密带 `@php-guard-encrypt` 标记的文件
+    marked_only: bool,
+}
+
+impl Pipeline {
+    fn new(options: &BuildOptions) -> Result<Pipeline> {
+        let mut builder = Encryptor::builder()
+            .algorithm(options.algorithm)
+            .compression(options.compression);
+        if let Some(chunk_size) = options.chunk_size {
+            builder = builder.chunked(chunk_size);
+        }
+        if let Some(path) = &options.sign_key {
+            builder = builder.signing_key(load_signing_key(path)?);
+        }
+        Ok(Pipeline {
+            encryptor: options.preprocess.configure(builder).build()?,
+            preprocess: options.preprocess.clone(),
+            marked_only: options.marked_only,
+        })
+    }
+
+    fn should_encrypt(&self, source: &[u8]) -> bool {
+        Markers::scan(source).should_encrypt(self.marked_only)
+    }
+
+    fn encrypt(&self, source: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.encryptor.encrypt(source)?)
+    }
+
+    fn key_id(&self) -> String {
+        self.encryptor.key().id_hex()
+    }
+
+    /// 影响输出内容的选项，任何一项变化都要求重新加密
+    fn cache_key(&self) -> String {
+        let sign = self
+            .encryptor
+            .signing_key()
+            .map(|key| signing::verifying_key_to_hex(&key.verifying_key()))
+            .unwrap_or_else(|| "none".to_string());
+        format!(
+            "header={};sign={};algorithm={};compress={};chunk={};marked_only={};{}",
+            bytes_to_hex(self.encryptor.header()),
+            sign,
+            self.encryptor.algorithm().name(),
+            self.encryptor.compression().name(),
+            self.encryptor.chunk_size().unwrap_or(0),
+            self.marked_only as u8,
+            self.preprocess.cache_key()
+        )
+    }
+}
+
+/// 加密文件的源码与输出总大小，用于报告压缩效果
+#[derive(Default)]
+struct SizeReport {
+    source: u64,
+    output: u64,
+}
+
+impl SizeReport {
+    /// 保留明文的文件不计入
+    fn add(&mut self, pipeline: &Pipeline, plaintext: &[u8], output: &[u8]) {
+        if pipeline.encryptor.is_encrypted(output) {
+            self.source += plaintext.len() as u64;
+            self.output += output.len() as u64;
+        }
+    }
+
+    fn saved_percent(&self) -> f64 {
+        (1.0 - self.output as f64 / self.source as f64) * 100.0
+    }
+}
+
+/// 缓存命中且输出文件未被改动时返回输出内容
+fn cached_output(
+    entry: &CacheEntry,
+    source_sha256: &str,
+    key_id: &str,
+    options: &str,
+    output_path: &Path,
+) -> Option<Vec<u8>> {
+    if entry.source_sha256 != source_sha256 || entry.key_id != key_id || entry.options != options {
+        return None;
+    }
+    let ciphertext = fs::read(output_path).ok()?;
+    (sha256_hex(&ciphertext) == entry.output_sha256).then_some(ciphertext)
+}
+
+struct Processed {
+    plaintext: Vec<u8>,
+    /// 写出的内容，保留明文时与 `plaintext` 相同
+    output: Vec<u8>,
+    encrypted: bool,
+}
+
+fn process_single_file(
+    path: &Path,
+    output_path: &Path,
+    pipeline: &Pipeline,
+) -> Result<Option<Processed>> {
     let content = fs::read(path)?;
 
-    if is_encrypted(&content) {
+    if pipeline.encryptor.is_encrypted(&content) {
         println!("{} 已加密，跳过: {}", "-".yellow(), path.display());
-        return Ok(false);
+        return Ok(None);
     }
 
-    // 创建备份文件
-    let backup_path = path.with_extension("php.bak");
-    if backup_path.exists() {
-        println!("{} 备份文件已存在: {}", "-".yellow(), backup_path.display());
-    } else {
-        fs::copy(path, &backup_path)?;
-        println!("{} 已创建备份: {}", "✓".green(), backup_path.display());
+    if !pipeline.should_encrypt(&content) {
+        if output_path != path {
+            write_file(output_path, &content)?;
+        }
+        println!("{} 保留明文: {}", "-".yellow(), output_path.display());
+        return Ok(Some(Processed {
+            output: content.clone(),
+            plaintext: content,
+            encrypted: false,
+        }));
     }
 
-    let encrypted = encrypt_content(&content);
+    // 原地加密时创建备份文件；使用 -o 时源文件不会被修改，也不在源目录中写入任何文件
+    if output_path == path {
+        let backup_path = path.with_extension("php.bak");
+        if backup_path.exists() {
+            println!("{} 备份文件已存在: {}", "-".yellow(), backup_path.display());
+        } else {
+            fs::copy(path, &backup_path)?;
+            println!("{} 已创建备份: {}", "✓".green(), backup_path.display());
+        }
+    }
 
-    let output_path = match output_dir {
-        Some(dir) => {
-            fs::create_dir_all(dir)?;
-            Path::new(dir).join(path.file_name().unwrap())
+    let encrypted = write_encrypted(&content, output_path, pipeline)?;
+    println!("{} 加密成功: {}", "✓".green(), output_path.display());
+
+    Ok(Some(Processed {
+        plaintext: content,
+        output: encrypted,
+        encrypted: true,
+    }))
+}
+
+fn write_file(path: &Path, content: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
+
+fn write_encrypted(content: &[u8], output_path: &Path, pipeline: &Pipeline) -> Result<Vec<u8>> {
+    let encrypted = pipeline.encrypt(content)?;
+    write_file(output_path, &encrypted)?;
+    Ok(encrypted)
+}
+
+pub struct WatchOptions {
+    pub output: String,
+    /// 事件合并窗口；无法监听文件系统事件时作为轮询间隔
+    pub interval_ms: u64,
+    pub build: BuildOptions,
+}
+
+type Snapshot = HashMap<PathBuf, (SystemTime, u64)>;
+
+fn snapshot(src: &Path, filter: &SourceFilter) -> Snapshot {
+    filter
+        .walk(src)
+        .filter_map(|path| {
+            let metadata = fs::metadata(&path).ok()?;
+            let modified = metadata.modified().ok()?;
+            Some((path, (modified, metadata.len())))
+        })
+        .collect()
+}
+
+type Events = Receiver<notify::Result<notify::Event>>;
+
+/// 监听源目录的文件系统事件 (inotify/FSEvents/ReadDirectoryChangesW)；失败时返回 `None`，
+/// 调用方退回到定时轮询
+fn watch_events(src: &Path) -> Option<(RecommendedWatcher, Events)> {
+    let (sender, events) = mpsc::channel();
+    let watcher = notify::recommended_watcher(sender).and_then(|mut watcher| {
+        watcher.watch(src, RecursiveMode::Recursive)?;
+        Ok(watcher)
+    });
+    match watcher {
+        Ok(watcher) => Some((watcher, events)),
+        Err(e) => {
+            eprintln!("{} 无法监听文件系统事件，改为定时轮询: {}", "!".yellow(), e);
+            None
         }
-        None => path.to_path_buf(),
+    }
+}
+
+/// 等到源目录有变化，再等 `interval` 内的后续事件一起处理 (保存文件、切换分支通常触发一串事件)。
+///
+/// 有待重试的文件时最多等一个 `interval`；没有事件来源时直接睡眠 `interval`。
+fn wait_for_changes(events: Option<&Events>, interval: Duration, retry: bool) {
+    let Some(events) = events else {
+        thread::sleep(interval);
+        return;
     };
+    let first = match retry {
+        true => events.recv_timeout(interval),
+        false => events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    };
+    match first {
+        Ok(_) => while events.recv_timeout(interval).is_ok() {},
+        Err(RecvTimeoutError::Timeout) => {}
+        Err(RecvTimeoutError::Disconnected) => thread::sleep(interval),
+    }
+}
 
-    fs::write(&output_path, &encrypted)?;
-    println!("{} 加密成功: {}", "✓".green(), output_path.display());
+/// 监视源目录，把新增、修改、改名和删除同步到输出目录。
+///
+/// 收到文件系统事件后重新扫描整个源目录 (每个文件一次 `stat`)，按修改时间和大小找出变化；
+/// 空闲时不扫描。改名表现为旧路径删除加新路径新增；源目录不会被修改，因此不创建备份文件。
+pub fn watch(src: &str, options: &WatchOptions) -> Result<()> {
+    println!("{}", "PHP-Guard 监视模式".green().bold());
+    println!("{}", "=".repeat(40));
 
-    Ok(true)
+    let src = Path::new(src);
+    if !src.is_dir() {
+        anyhow::bail!("源目录不存在: {}", src.display());
+    }
+    let output = Path::new(&options.output);
+    let pipeline = Pipeline::new(&options.build)?;
+    let filter = SourceFilter::new(&options.build.exclude);
+    let linter = match options.build.lint.lint {
+        true => Some(options.build.lint.linter()?),
+        false => None,
+    };
+
+    println!(
+        "{} 监视 {} -> {} (按 Ctrl-C 退出)",
+        "✓".green(),
+        src.display(),
+        output.display()
+    );
+
+    let interval = Duration::from_millis(options.interval_ms);
+    let watcher = watch_events(src);
+    let events = watcher.as_ref().map(|(_, events)| events);
+    let mut previous = Snapshot::new();
+    loop {
+        let mut current = snapshot(src, &filter);
+        let mut failed = Vec::new();
+
+        for (path, stamp) in &current {
+            if previous.get(path) == Some(stamp) {
+                continue;
+            }
+            let output_path = output.join(path.strip_prefix(src)?);
+            if let Some(linter) = &linter {
+                match linter.check(path) {
+                    Ok(None) => {}
+                    // 保留旧的输出，等下次修改再处理
+                    Ok(Some(error)) => {
+                        eprintln!(
+                            "{} {}失败: {}:{}",
+                            "✗".red(),
+                            linter.name(),
+                            path.display(),
+                            error
+                        );
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("{} 加密失败: {}: {}", "✗".red(), path.display(), e);
+                        failed.push(path.clone());
+                        continue;
+                    }
+                }
+            }
+            let result = fs::read(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|content| {
+                    if pipeline.encryptor.is_encrypted(&content) {
+                        println!("{} 已加密，跳过: {}", "-".yellow(), path.display());
+                        return Ok(());
+                    }
+                    if !pipeline.should_encrypt(&content) {
+                        write_file(&output_path, &content)?;
+                        println!("{} 保留明文: {}", "-".yellow(), output_path.display());
+                        return Ok(());
+                    }
+                    write_encrypted(&content, &output_path, &pipeline)?;
+                    println!("{} 加密成功: {}", "✓".green(), output_path.display());
+                    Ok(())
+                });
+            // 文件可能正在写入，下一轮再试，不中断监视
+            if let Err(e) = result {
+                eprintln!("{} 加密失败: {}: {}", "✗".red(), path.display(), e);
+                failed.push(path.clone());
+            }
+        }
+
+        for path in previous.keys().filter(|path| !current.contains_key(*path)) {
+            let output_path = output.join(path.strip_prefix(src)?);
+            if output_path.is_file() {
+                match fs::remove_file(&output_path) {
+                    Ok(()) => println!("{} 已移除: {}", "-".yellow(), output_path.display()),
+                    Err(e) => eprintln!("{} 移除失败: {}: {}", "✗".red(), output_path.display(), e),
+                }
+            }
+        }
+
+        // 失败的文件不计入快照，下一轮重新处理；输出保留，不当作删除
+        let retry = !failed.is_empty();
+        for path in failed {
+            current.insert(path, (SystemTime::UNIX_EPOCH, u64::MAX));
+        }
+        previous = current;
+        wait_for_changes(events, interval, retry);
+    }
 }
 
 pub fn check(paths: &[String]) -> Result<()> {
@@ -90,7 +606,7 @@ pub fn check(paths: &[String]) -> Result<()> {
         let path_obj = Path::new(path);
         if path_obj.is_file() {
             total += 1;
-            if check_single_file(&path_obj)? {
+            if check_single_file(path_obj)? {
                 encrypted_count += 1;
             }
         } else if path_obj.is_dir() {
@@ -122,6 +638,81 @@ pub fn check(paths: &[String]) -> Result<()> {
     Ok(())
 }
 
+pub fn inspect(paths: &[String]) -> Result<()> {
+    println!("{}", "PHP-Guard 文件分析".green().bold());
+    println!("{}", "=".repeat(40));
+
+    let verifying_key = signing::build_verifying_key()?;
+    let mut problems = 0;
+
+    for path in paths {
+        let data = fs::read(path)?;
+        let class = classify_verified(&data, verifying_key.as_ref());
+
+        println!("\n文件: {}", path);
+        println!("大小: {} 字节", data.len());
+        let kind = match &class {
+            FileClass::Plaintext => "明文".to_string(),
+            FileClass::Legacy { payload_len } => format!("旧格式 (XOR)，密文 {} 字节", payload_len),
+            FileClass::Container(_) => "容器格式".to_string(),
+            FileClass::UnknownMagic => "未知格式 (可能由其他构建加密)".to_string(),
+            FileClass::Truncated => "文件被截断".to_string(),
+            FileClass::WrongKey(_) => "密钥不匹配".to_string(),
+            FileClass::Corrupt { reason, .. } => format!("已损坏: {}", reason),
+        };
+        println!("类型: {} [{}]", kind, class.name());
+
+        if let Some(info) = class.container() {
+            let header = &info.header;
+            println!("版本: {}", header.version);
+            println!(
+                "算法: {} ({})",
+                algorithm_name(header.algorithm).unwrap_or("unknown"),
+                header.algorithm
+            );
+            let key_status = if header.key_id == key_id_bytes() {
+                "与当前密钥一致".green()
+            } else {
+                format!("当前密钥为 {}", key_id()).red()
+            };
+            println!(
+                "密钥标识: {} ({})",
+                bytes_to_hex(&header.key_id),
+                key_status
+            );
+            let flags = flag_names(header.flags);
+            match flags.is_empty() {
+                true => println!("标志: -"),
+                false => println!("标志: {}", flags.join(", ")),
+            }
+            println!("密文: {} 字节", info.payload_len);
+            if info.signed {
+                let signature = match info.signature_valid {
+                    Some(true) => "有效".green(),
+                    Some(false) => "无效".red(),
+                    None => "未校验 (未配置公钥)".yellow(),
+                };
+                println!("签名: {} 字节，{}", SIGNATURE_LENGTH, signature);
+            }
+        }
+
+        let status = match (&class, class.is_decryptable()) {
+            (FileClass::Plaintext, _) => format!("{} 未加密", "-".yellow()),
+            (_, true) => format!("{} 可以解密", "✓".green()),
+            (_, false) => {
+                problems += 1;
+                format!("{} 无法解密", "✗".red())
+            }
+        };
+        println!("状态: {}", status);
+    }
+
+    if problems > 0 {
+        anyhow::bail!("{} 个文件无法解密", problems);
+    }
+    Ok(())
+}
+
 fn check_single_file(path: &Path) -> Result<bool> {
     let content = fs::read(path)?;
     let is_enc = is_encrypted(&content);
@@ -138,32 +729,19 @@ pub fn decrypt(paths: &[String], output_dir: Option<&str>) -> Result<()> {
     println!("{}", "PHP-Guard 文件解密".green().bold());
     println!("{}", "=".repeat(40));
 
+    let decryptor = Decryptor::default();
     let mut total = 0;
     let mut skipped = 0;
 
-    for path in paths {
-        let path_obj = Path::new(path);
-        if path_obj.is_file() {
-            match decrypt_single_file(&path_obj, output_dir)? {
-                true => total += 1,
-                false => skipped += 1,
-            }
-        } else if path_obj.is_dir() {
-            for entry in walkdir::WalkDir::new(path_obj)
-                .into_iter()
-                .filter_map(|e| e.ok())
-                .filter(|e| {
-                    e.path()
-                        .extension()
-                        .map(|ext| ext == "php")
-                        .unwrap_or(false)
-                })
-            {
-                match decrypt_single_file(entry.path(), output_dir)? {
-                    true => total += 1,
-                    false => skipped += 1,
-                }
-            }
+    // 与 encrypt 相同，-o 时保持源目录内部的结构
+    for (source, relative) in collect_sources(paths, &SourceFilter::default()) {
+        let output_path = match output_dir {
+            Some(dir) => Path::new(dir).join(&relative),
+            None => source.clone(),
+        };
+        match decrypt_single_file(&decryptor, &source, &output_path)? {
+            true => total += 1,
+            false => skipped += 1,
         }
     }
 
@@ -175,27 +753,290 @@ pub fn decrypt(paths: &[String], output_dir: Option<&str>) -> Result<()> {
     Ok(())
 }
 
-fn decrypt_single_file(path: &Path, output_dir: Option<&str>) -> Result<bool> {
+fn decrypt_single_file(decryptor: &Decryptor, path: &Path, output_path: &Path) -> Result<bool> {
     let content = fs::read(path)?;
 
-    if !is_encrypted(&content) {
+    if !decryptor.is_encrypted(&content) {
         println!("{} 未加密，跳过: {}", "-".yellow(), path.display());
         return Ok(false);
     }
 
-    let decrypted = read_and_decrypt_file(path)
-        .map_err(|e| anyhow::anyhow!("解密失败: {}: {}", path.display(), e))?;
+    let decrypted = decryptor
+        .decrypt(&content)
+        .with_context(|| format!("解密失败: {}", path.display()))?;
 
-    let output_path = match output_dir {
-        Some(dir) => {
-            fs::create_dir_all(dir)?;
-            Path::new(dir).join(path.file_name().unwrap())
+    write_file(output_path, &decrypted)?;
+    println!("{} 解密成功: {}", "✓".green(), output_path.display());
+
+    Ok(true)
+}
+
+pub fn keygen(output: &str) -> Result<()> {
+    let path = Path::new(output);
+    if path.exists() {
+        anyhow::bail!("签名私钥已存在: {}", path.display());
+    }
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+
+    let key = signing::generate_signing_key()?;
+    write_private_file(path, signing::signing_key_to_hex(&key).as_bytes())?;
+
+    println!("{} 签名私钥已生成: {}", "✓".green(), path.display());
+    println!("\n将公钥加入 .php-guard/config.env 后重新编译扩展:");
+    println!(
+        "export PHP_GUARD_SIGN_PUBKEY=\"{}\"",
+        signing::verifying_key_to_hex(&key.verifying_key())
+    );
+    println!("\n{} 私钥只用于 CLI 签名，不要随扩展分发", "!".yellow());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_private_file(path: &Path, content: &[u8]) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(content)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_private_file(path: &Path, content: &[u8]) -> Result<()> {
+    fs::write(path, content)?;
+    Ok(())
+}
+
+fn parse_sources(sources: &[String]) -> Result<Vec<FingerprintSource>> {
+    fingerprint::parse_sources(&sources.join(",")).map_err(|e| anyhow::anyhow!(e))
+}
+
+pub fn fingerprint(sources: &[String], custom: Option<&str>) -> Result<()> {
+    let sources = parse_sources(sources)?;
+    let value = fingerprint::compute(&sources, custom)
+        .map_err(|e| anyhow::anyhow!("无法计算主机指纹: {}", e))?;
+    println!("{}", value);
+    Ok(())
+}
+
+pub struct LicenseOptions {
+    pub customer: String,
+    pub expires: Option<String>,
+    pub grace_days: u32,
+    pub features: Vec<String>,
+    pub hosts: Vec<String>,
+    pub bind: Vec<String>,
+    pub domains: Vec<String>,
+    pub sapis: Vec<String>,
+    pub sign_key: String,
+}
+
+pub fn license_issue(options: &LicenseOptions, output: Option<&str>) -> Result<()> {
+    let mut license = License::new(&options.customer);
+    license.expires = options.expires.as_deref().map(Date::parse).transpose()?;
+    license.grace_days = options.grace_days;
+    license.features = options.features.clone();
+    license.hosts = options.hosts.clone();
+    license.bind = parse_sources(&options.bind)?;
+    license.domains = options.domains.clone();
+    license.sapis = options.sapis.clone();
+
+    let key = load_signing_key(&options.sign_key)?;
+    match signing::build_verifying_key()? {
+        Some(public) if public != key.verifying_key() => anyhow::bail!(
+            "签名私钥与构建配置的 PHP_GUARD_SIGN_PUBKEY 不匹配，扩展无法校验这份授权: {}",
+            options.sign_key
+        ),
+        Some(_) => {}
+        None => eprintln!(
+            "{} 构建配置未设置 PHP_GUARD_SIGN_PUBKEY，扩展无法校验授权，请先运行 keygen",
+            "!".yellow()
+        ),
+    }
+    let content = license.issue(&key);
+    match output {
+        Some(path) => {
+            fs::write(path, &content)?;
+            println!("{} 授权文件已生成: {}", "✓".green(), path);
         }
-        None => path.to_path_buf(),
+        None => print!("{}", content),
+    }
+
+    Ok(())
+}
+
+pub fn license_inspect(path: &str) -> Result<()> {
+    println!("{}", "PHP-Guard 授权检查".green().bold());
+    println!("{}", "=".repeat(40));
+
+    let text = fs::read_to_string(path)?;
+    let license =
+        License::parse(&text).map_err(|e| anyhow::anyhow!("授权无效: {}: {}", path, e))?;
+
+    for line in license_fields(&license) {
+        println!("{}", line);
+    }
+
+    let status = match license.status(Date::today()) {
+        LicenseStatus::Valid => format!("{} 有效", "✓".green()),
+        LicenseStatus::Grace { days_left } => {
+            format!("{} 已过期，宽限期剩余 {} 天", "-".yellow(), days_left)
+        }
+        LicenseStatus::Expired => format!("{} 已过期", "✗".red()),
     };
+    println!("状态: {}", status);
 
-    fs::write(&output_path, decrypted)?;
-    println!("{} 解密成功: {}", "✓".green(), output_path.display());
+    Ok(())
+}
 
-    Ok(true)
+/// `license inspect` 显示的字段，限制条件为空时明确写出不限制
+fn license_fields(license: &License) -> Vec<String> {
+    let list = |values: &[String], empty: &str| match values.is_empty() {
+        true => empty.to_string(),
+        false => values.join(", "),
+    };
+
+    let mut lines = vec![format!("客户: {}", license.customer)];
+    match license.expires {
+        Some(expires) => lines.push(format!("到期: {}", expires)),
+        None => lines.push("到期: 永久".to_string()),
+    }
+    lines.push(format!("宽限期: {} 天", license.grace_days));
+    lines.push(format!("功能: {}", list(&license.features, "-")));
+    lines.push(format!("密钥: {}", license.key_id));
+    if license.hosts.is_empty() {
+        lines.push("主机绑定: 不限制".to_string());
+    } else {
+        let bind: Vec<&str> = license.bind_sources().iter().map(|s| s.as_str()).collect();
+        lines.push(format!(
+            "主机绑定: {} ({})",
+            license.hosts.join(", "),
+            bind.join(",")
+        ));
+    }
+    lines.push(format!("域名: {}", list(&license.domains, "不限制")));
+    lines.push(format!("SAPI: {}", list(&license.sapis, "不限制")));
+    lines
+}
+
+pub fn audit(dir: &str, manifest_path: &str) -> Result<()> {
+    println!("{}", "PHP-Guard 部署审计".green().bold());
+    println!("{}", "=".repeat(40));
+
+    let manifest = Manifest::load(Path::new(manifest_path))
+        .map_err(|e| anyhow::anyhow!("无法读取清单: {}: {}", manifest_path, e))?;
+    let issues = manifest.audit(Path::new(dir))?;
+
+    for issue in &issues {
+        match issue {
+            AuditIssue::Modified(path) => println!("{} 已修改: {}", "✗".red(), path),
+            AuditIssue::Missing(path) => println!("{} 缺失: {}", "✗".red(), path),
+            AuditIssue::Added(path) => println!("{} 新增: {}", "✗".red(), path),
+        }
+    }
+
+    if issues.is_empty() {
+        println!(
+            "{} 审计通过: {} 个文件与清单一致",
+            "✓".green(),
+            manifest.files.len()
+        );
+        Ok(())
+    } else {
+        anyhow::bail!("审计失败: {} 处不一致", issues.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::FromArgMatches;
+    use php_guard_core::encrypt_content;
+
+    fn build_options() -> BuildOptions {
+        let matches =
+            BuildOptions::augment_args(clap::Command::new("test")).get_matches_from(["test"]);
+        BuildOptions::from_arg_matches(&matches).unwrap()
+    }
+
+    #[test]
+    fn test_output_dir_leaves_source_tree_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("src/a.php");
+        write_file(&source, b"<?php echo 1;").unwrap();
+        let output = dir.path().join("dist/a.php");
+
+        let pipeline = Pipeline::new(&build_options()).unwrap();
+        let processed = process_single_file(&source, &output, &pipeline)
+            .unwrap()
+            .unwrap();
+        assert!(processed.encrypted);
+        assert_eq!(fs::read(&output).unwrap(), processed.output);
+        assert_eq!(
+            fs::read_dir(dir.path().join("src")).unwrap().count(),
+            1,
+            "no backup next to the source"
+        );
+
+        // 原地加密仍然保留备份
+        process_single_file(&source, &source, &pipeline).unwrap();
+        assert!(dir.path().join("src/a.php.bak").is_file());
+    }
+
+    #[test]
+    fn test_decrypt_output_keeps_relative_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        for name in ["a/index.php", "b/index.php"] {
+            let source = format!("<?php echo '{}';", name);
+            write_file(
+                &src.join(name),
+                &encrypt_content(source.as_bytes()).unwrap(),
+            )
+            .unwrap();
+        }
+        let dist = dir.path().join("dist");
+
+        decrypt(
+            &[src.to_string_lossy().into_owned()],
+            Some(&dist.to_string_lossy()),
+        )
+        .unwrap();
+        for name in ["a/index.php", "b/index.php"] {
+            let expected = format!("<?php echo '{}';", name);
+            assert_eq!(fs::read(dist.join(name)).unwrap(), expected.as_bytes());
+        }
+        assert!(!dist.join("index.php").exists());
+    }
+
+    #[test]
+    fn test_license_fields_show_restrictions() {
+        let mut license = License::new("ACME Inc");
+        license.expires = Date::from_ymd(2027, 1, 31);
+        license.features = vec!["reports".to_string()];
+        license.domains = vec!["shop.example.com".to_string(), "*.example.org".to_string()];
+        license.sapis = vec!["fpm-fcgi".to_string()];
+
+        let lines = license_fields(&license);
+        assert!(lines.contains(&"到期: 2027-01-31".to_string()));
+        assert!(lines.contains(&"功能: reports".to_string()));
+        assert!(lines.contains(&"域名: shop.example.com, *.example.org".to_string()));
+        assert!(lines.contains(&"SAPI: fpm-fcgi".to_string()));
+        assert!(lines.contains(&"主机绑定: 不限制".to_string()));
+
+        // 没有限制时明确显示不限制，而不是省略该行
+        let lines = license_fields(&License::new("ACME Inc"));
+        assert!(lines.contains(&"到期: 永久".to_string()));
+        assert!(lines.contains(&"功能: -".to_string()));
+        assert!(lines.contains(&"域名: 不限制".to_string()));
+        assert!(lines.contains(&"SAPI: 不限制".to_string()));
+    }
 }
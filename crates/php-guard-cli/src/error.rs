@@ -0,0 +1,65 @@
+use anyhow::Error as AnyError;
+use php_guard_core::Error;
+
+/// 未归类的错误使用的退出码；2 由 clap 用于参数错误
+const EXIT_FAILURE: i32 = 1;
+/// 核心错误的退出码为 `EXIT_CORE_BASE + Error::code()`，避开 clap 使用的 2
+const EXIT_CORE_BASE: i32 = 10;
+
+fn core_error(e: &AnyError) -> Option<&Error> {
+    e.chain().find_map(|cause| cause.downcast_ref::<Error>())
+}
+
+fn localize(e: &Error) -> String {
+    match e {
+        Error::NotEncrypted => "文件未加密或头部不匹配".to_string(),
+        Error::AlreadyEncrypted => "文件已加密".to_string(),
+        Error::Truncated => "文件不完整".to_string(),
+        Error::UnsupportedVersion(version) => format!("不支持的容器版本 {}", version),
+        Error::UnsupportedAlgorithm(algorithm) => format!("不支持的加密算法 {}", algorithm),
+        Error::UnknownKey => "文件由其他密钥加密".to_string(),
+        Error::NotSigned => "文件未签名".to_string(),
+        Error::AuthenticationFailed => "签名校验失败".to_string(),
+        Error::Config(message) => format!("构建配置无效: {}", message),
+        Error::Io(e) => e.to_string(),
+        Error::Corrupt(message) => format!("文件已损坏: {}", message),
+        Error::ChunkAuthenticationFailed(index) => format!("第 {} 块校验失败", index),
+    }
+}
+
+pub fn exit_code(e: &AnyError) -> i32 {
+    match core_error(e) {
+        Some(core) => EXIT_CORE_BASE + core.code(),
+        None => EXIT_FAILURE,
+    }
+}
+
+/// 按错误链逐层输出，核心错误换成中文描述
+pub fn describe(e: &AnyError) -> String {
+    e.chain()
+        .map(|cause| match cause.downcast_ref::<Error>() {
+            Some(core) => localize(core),
+            None => cause.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_core_error_mapping() {
+        let result: anyhow::Result<()> =
+            Err(Error::AuthenticationFailed).context("解密失败: a.php");
+        let e = result.unwrap_err();
+        assert_eq!(exit_code(&e), 18);
+        assert_eq!(describe(&e), "解密失败: a.php: 签名校验失败");
+
+        let other = anyhow::anyhow!("签名私钥已存在");
+        assert_eq!(exit_code(&other), EXIT_FAILURE);
+        assert_eq!(describe(&other), "签名私钥已存在");
+    }
+}
@@ -0,0 +1,146 @@
+//! `encrypt` 与 `watch` 共用的源文件筛选规则。
+//!
+//! 只处理 `.php` 文件；`--exclude` 模式语法:
+//! - 不含 `/` 的模式匹配任意一级文件或目录名，如 `vendor`、`*.tpl.php`
+//! - 含 `/` 的模式从源目录开始匹配，如 `config/local.php`、`tests/**/fixtures`
+//! - `*` 与 `?` 不跨越 `/`，`**` 匹配任意多级目录
+//!
+//! 目录被排除时其下所有文件都被排除。
+
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Clone, Default)]
+pub struct SourceFilter {
+    excludes: Vec<Vec<String>>,
+}
+
+impl SourceFilter {
+    pub fn new(excludes: &[String]) -> SourceFilter {
+        SourceFilter {
+            excludes: excludes
+                .iter()
+                .map(|pattern| {
+                    pattern
+                        .trim_start_matches("./")
+                        .trim_matches('/')
+                        .split('/')
+                        .map(str::to_string)
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    /// `relative` 为相对源目录的路径
+    pub fn includes(&self, relative: &Path) -> bool {
+        relative.extension().is_some_and(|ext| ext == "php") && !self.is_excluded(relative)
+    }
+
+    pub fn is_excluded(&self, relative: &Path) -> bool {
+        let components: Vec<String> = relative
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+
+        self.excludes
+            .iter()
+            .any(|pattern| match pattern.as_slice() {
+                [name] if name != "**" => components.iter().any(|c| glob_match(name, c)),
+                _ => (1..=components.len()).any(|n| match_segments(pattern, &components[..n])),
+            })
+    }
+
+    /// 遍历目录下符合规则的文件，被排除的目录不会进入
+    pub fn walk<'a>(&'a self, dir: &'a Path) -> impl Iterator<Item = PathBuf> + 'a {
+        walkdir::WalkDir::new(dir)
+            .into_iter()
+            .filter_entry(move |e| {
+                let relative = e.path().strip_prefix(dir).unwrap_or(e.path());
+                relative.as_os_str().is_empty() || !self.is_excluded(relative)
+            })
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter(move |e| {
+                e.path()
+                    .strip_prefix(dir)
+                    .is_ok_and(|relative| self.includes(relative))
+            })
+            .map(|e| e.into_path())
+    }
+}
+
+fn match_segments(pattern: &[String], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| match_segments(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path)) => glob_match(first, name) && match_segments(rest, path),
+            None => false,
+        },
+    }
+}
+
+/// 单级名称的通配符匹配，支持 `*` 和 `?`
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclude_patterns() {
+        let filter = SourceFilter::new(&[
+            "vendor".to_string(),
+            "*.tpl.php".to_string(),
+            "config/local.php".to_string(),
+            "tests/**/fixtures".to_string(),
+        ]);
+
+        assert!(filter.includes(Path::new("index.php")));
+        assert!(filter.includes(Path::new("src/config/local.php")));
+        assert!(filter.includes(Path::new("tests/UnitTest.php")));
+        assert!(!filter.includes(Path::new("readme.md")));
+        assert!(!filter.includes(Path::new("vendor/autoload.php")));
+        assert!(!filter.includes(Path::new("lib/vendor/x.php")));
+        assert!(!filter.includes(Path::new("views/home.tpl.php")));
+        assert!(!filter.includes(Path::new("config/local.php")));
+        assert!(!filter.includes(Path::new("tests/fixtures/a.php")));
+        assert!(!filter.includes(Path::new("tests/unit/deep/fixtures/a.php")));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.php", "a.php"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("*.php", "a.php.bak"));
+        assert!(!glob_match("a?c", "ac"));
+    }
+}
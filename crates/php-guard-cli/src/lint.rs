@@ -0,0 +1,192 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Result;
+use clap::Args;
+
+use php_guard_core::{check_delimiters, is_encrypted};
+
+/// 加密前的语法检查，`encrypt` 与 `watch` 共用
+#[derive(Debug, Clone, Default, Args)]
+pub struct LintOptions {
+    #[arg(
+        long,
+        help = "Check PHP syntax with `php -l` before encrypting and refuse the build on errors \
+                (php -l reports line numbers only)"
+    )]
+    pub lint: bool,
+    #[arg(
+        long,
+        requires = "lint",
+        help = "PHP binary used for `php -l` (default: php on PATH)"
+    )]
+    pub php_binary: Option<String>,
+    #[arg(
+        long,
+        requires = "lint",
+        conflicts_with = "php_binary",
+        help = "Instead of `php -l`, only check for unclosed strings, comments, heredocs \
+                and unbalanced brackets (not a syntax check)"
+    )]
+    pub lint_delimiters_only: bool,
+}
+
+/// 实际使用的检查方式，构建开始前确定一次
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Linter {
+    Php(PathBuf),
+    Delimiters,
+}
+
+impl LintOptions {
+    /// 默认使用 PATH 中的 php；找不到时报错而不是退回到只检查括号的内置检查器，
+    /// 避免 `--lint` 在缺少 php 的机器上放过语法错误
+    pub fn linter(&self) -> Result<Linter> {
+        self.linter_with(env::var_os("PATH").as_deref())
+    }
+
+    fn linter_with(&self, path: Option<&std::ffi::OsStr>) -> Result<Linter> {
+        if self.lint_delimiters_only {
+            return Ok(Linter::Delimiters);
+        }
+        if let Some(php) = &self.php_binary {
+            return Ok(Linter::Php(PathBuf::from(php)));
+        }
+        match path.and_then(|path| find_in_path(path, "php")) {
+            Some(php) => Ok(Linter::Php(php)),
+            None => anyhow::bail!(
+                "--lint 需要 php 做语法检查，但 PATH 中找不到 php；\
+                 用 --php-binary 指定，或用 --lint-delimiters-only 只检查字符串和括号"
+            ),
+        }
+    }
+}
+
+impl Linter {
+    /// 输出中的检查名称：分隔符检查不是语法检查，不能报告为“语法检查通过”
+    pub fn name(&self) -> &'static str {
+        match self {
+            Linter::Php(_) => "语法检查",
+            Linter::Delimiters => "分隔符检查",
+        }
+    }
+
+    /// 检查源文件，发现错误时返回 `Ok(Some(_))`：`php -l` 为 `"行: 信息"`，分隔符检查为
+    /// `"行:列: 信息"`。已加密的文件不检查。
+    pub fn check(&self, path: &Path) -> Result<Option<String>> {
+        let content = fs::read(path)?;
+        if is_encrypted(&content) {
+            return Ok(None);
+        }
+        match self {
+            Linter::Php(php) => php_lint(php, path),
+            Linter::Delimiters => Ok(check_delimiters(&content).err().map(|e| e.to_string())),
+        }
+    }
+}
+
+fn find_in_path(path: &std::ffi::OsStr, name: &str) -> Option<PathBuf> {
+    env::split_paths(path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// `php -l` 只报告行号，不报告列号
+fn php_lint(php: &Path, path: &Path) -> Result<Option<String>> {
+    let output = Command::new(php)
+        .args(["-d", "display_errors=1", "-d", "log_errors=0", "-l"])
+        .arg(path)
+        .output()
+        .map_err(|e| anyhow::anyhow!("无法运行 {}: {}", php.display(), e))?;
+    if output.status.success() {
+        return Ok(None);
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let message = stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(str::trim)
+        .find(|line| line.contains("error"))
+        .unwrap_or("php -l failed");
+
+    Ok(Some(match message.rsplit_once(" on line ") {
+        Some((text, line)) => {
+            let text = text
+                .rsplit_once(" in ")
+                .map_or(text, |(text, _)| text)
+                .trim_start_matches("PHP ");
+            format!("{}: {}", line.trim(), text)
+        }
+        None => message.to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint_options() -> LintOptions {
+        LintOptions {
+            lint: true,
+            ..LintOptions::default()
+        }
+    }
+
+    /// PATH 中放一个输出固定 `php -l` 错误的假 php，测试不依赖本机是否安装了 php
+    #[cfg(unix)]
+    fn fake_php(dir: &Path) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let php = dir.join("php");
+        fs::write(
+            &php,
+            "#!/bin/sh\n\
+             for f; do :; done\n\
+             echo \"PHP Parse error:  syntax error, unexpected '}' in $f on line 2\"\n\
+             echo \"Errors parsing $f\"\n\
+             exit 255\n",
+        )
+        .unwrap();
+        fs::set_permissions(&php, fs::Permissions::from_mode(0o755)).unwrap();
+        php
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_default_mode_rejects_missing_semicolon() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("a.php");
+        fs::write(&source, "<?php\nfunction f() { return 1 }\n").unwrap();
+
+        // 内置检查器只看括号，放过缺少分号的源码，因此不能作为默认方式
+        let delimiters = LintOptions {
+            lint_delimiters_only: true,
+            ..lint_options()
+        };
+        assert_eq!(delimiters.linter().unwrap(), Linter::Delimiters);
+        assert_eq!(Linter::Delimiters.check(&source).unwrap(), None);
+        assert_eq!(Linter::Delimiters.name(), "分隔符检查");
+
+        // 默认使用 PATH 中的 php，没有 php 时拒绝构建
+        let empty = tempfile::tempdir().unwrap();
+        let error = lint_options()
+            .linter_with(Some(empty.path().as_os_str()))
+            .unwrap_err();
+        assert!(error.to_string().contains("PATH"));
+
+        let bin = tempfile::tempdir().unwrap();
+        let php = fake_php(bin.path());
+        let linter = lint_options()
+            .linter_with(Some(bin.path().as_os_str()))
+            .unwrap();
+        assert_eq!(linter, Linter::Php(php));
+        assert_eq!(
+            linter.check(&source).unwrap().as_deref(),
+            Some("2: Parse error:  syntax error, unexpected '}'")
+        );
+    }
+}
@@ -1,7 +1,16 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use colored::Colorize;
 
+mod build_cache;
 mod commands;
+mod error;
+mod filter;
+mod lint;
+mod manifest;
+mod markers;
+mod obfuscate;
+mod preprocess;
 
 #[derive(Parser)]
 #[command(name = "php-guard")]
@@ -19,12 +28,37 @@ enum Commands {
         paths: Vec<String>,
         #[arg(short, long)]
         output: Option<String>,
+        #[arg(long, help = "Write a JSON manifest of the encrypted files")]
+        manifest: Option<String>,
+        #[arg(long, help = "Ignore the build cache and re-encrypt every file")]
+        force: bool,
+        #[command(flatten)]
+        build: commands::BuildOptions,
+    },
+    #[command(about = "Watch a source tree and keep an encrypted mirror up to date")]
+    Watch {
+        src: String,
+        #[arg(short, long)]
+        output: String,
+        #[arg(
+            long,
+            default_value_t = 500,
+            help = "Milliseconds to batch file system events; also the polling interval when events are unavailable"
+        )]
+        interval: u64,
+        #[command(flatten)]
+        build: commands::BuildOptions,
     },
     #[command(about = "Check if files are encrypted")]
     Check {
         #[arg(required = true)]
         paths: Vec<String>,
     },
+    #[command(about = "Show the format, key id and integrity of encrypted files")]
+    Inspect {
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
     #[command(about = "Decrypt PHP files")]
     Decrypt {
         #[arg(required = true)]
@@ -32,21 +66,173 @@ enum Commands {
         #[arg(short, long)]
         output: Option<String>,
     },
+    #[command(about = "Compare a deployed tree against a build manifest")]
+    Audit {
+        dir: String,
+        #[arg(long)]
+        manifest: String,
+    },
+    #[command(about = "Generate an Ed25519 key pair for signing encrypted files")]
+    Keygen {
+        #[arg(short, long, default_value = ".php-guard/signing.key")]
+        output: String,
+    },
+    #[command(about = "Print this host's fingerprint for license binding")]
+    Fingerprint {
+        #[arg(
+            long,
+            value_delimiter = ',',
+            default_value = "machine-id",
+            help = "Sources: machine-id, mac, hostname, custom"
+        )]
+        source: Vec<String>,
+        #[arg(long, help = "Customer-supplied value for the custom source")]
+        custom: Option<String>,
+    },
+    #[command(about = "Issue or inspect license files")]
+    License {
+        #[command(subcommand)]
+        command: LicenseCommands,
+    },
 }
 
-fn main() -> Result<()> {
+#[derive(Subcommand)]
+enum LicenseCommands {
+    #[command(about = "Issue a signed license file")]
+    Issue {
+        #[arg(long)]
+        customer: String,
+        #[arg(long, help = "Expiry date (YYYY-MM-DD), omit for a perpetual license")]
+        expires: Option<String>,
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Days protected code keeps running after expiry"
+        )]
+        grace_days: u32,
+        #[arg(long, value_delimiter = ',')]
+        features: Vec<String>,
+        #[arg(
+            long = "host",
+            help = "Allowed host fingerprint, repeat for several hosts"
+        )]
+        hosts: Vec<String>,
+        #[arg(
+            long,
+            value_delimiter = ',',
+            help = "Fingerprint sources used by --host (default: machine-id)"
+        )]
+        bind: Vec<String>,
+        #[arg(
+            long = "domain",
+            help = "Allowed HTTP_HOST/SERVER_NAME (supports *.example.com), repeatable"
+        )]
+        domains: Vec<String>,
+        #[arg(
+            long = "sapi",
+            help = "Allowed SAPI such as fpm-fcgi or cli, repeatable"
+        )]
+        sapis: Vec<String>,
+        #[arg(
+            long,
+            default_value = ".php-guard/signing.key",
+            help = "Ed25519 signing key file created by `keygen`"
+        )]
+        sign_key: String,
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    #[command(about = "Verify a license file and show its contents")]
+    Inspect { path: String },
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{} {}", "错误:".red(), error::describe(&e));
+        std::process::exit(error::exit_code(&e));
+    }
+}
+
+fn run() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Encrypt { paths, output } => {
-            commands::encrypt(&paths, output.as_deref())?;
+        Commands::Encrypt {
+            paths,
+            output,
+            manifest,
+            force,
+            build,
+        } => {
+            let options = commands::EncryptOptions {
+                output,
+                manifest,
+                force,
+                build,
+            };
+            commands::encrypt(&paths, &options)?;
+        }
+        Commands::Watch {
+            src,
+            output,
+            interval,
+            build,
+        } => {
+            let options = commands::WatchOptions {
+                output,
+                interval_ms: interval,
+                build,
+            };
+            commands::watch(&src, &options)?;
         }
         Commands::Check { paths } => {
             commands::check(&paths)?;
         }
+        Commands::Inspect { paths } => {
+            commands::inspect(&paths)?;
+        }
         Commands::Decrypt { paths, output } => {
             commands::decrypt(&paths, output.as_deref())?;
         }
+        Commands::Audit { dir, manifest } => {
+            commands::audit(&dir, &manifest)?;
+        }
+        Commands::Keygen { output } => {
+            commands::keygen(&output)?;
+        }
+        Commands::Fingerprint { source, custom } => {
+            commands::fingerprint(&source, custom.as_deref())?;
+        }
+        Commands::License { command } => match command {
+            LicenseCommands::Issue {
+                customer,
+                expires,
+                grace_days,
+                features,
+                hosts,
+                bind,
+                domains,
+                sapis,
+                sign_key,
+                output,
+            } => {
+                let options = commands::LicenseOptions {
+                    customer,
+                    expires,
+                    grace_days,
+                    features,
+                    hosts,
+                    bind,
+                    domains,
+                    sapis,
+                    sign_key,
+                };
+                commands::license_issue(&options, output.as_deref())?;
+            }
+            LicenseCommands::Inspect { path } => {
+                commands::license_inspect(&path)?;
+            }
+        },
     }
 
     Ok(())
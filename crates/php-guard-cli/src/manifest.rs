@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use php_guard_core::crypto::{bytes_to_hex, key_id, sha256_hex};
+use php_guard_core::format::ContainerHeader;
+use php_guard_core::is_encrypted;
+
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// 相对部署根目录的路径，统一使用 `/` 分隔
+    pub path: String,
+    /// 加密后文件大小
+    pub size: u64,
+    /// 保留明文的文件没有密钥标识
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    pub plaintext_sha256: String,
+    pub ciphertext_sha256: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub key_id: String,
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditIssue {
+    Modified(String),
+    Missing(String),
+    Added(String),
+}
+
+pub fn manifest_path(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl Manifest {
+    pub fn new() -> Manifest {
+        Manifest {
+            version: MANIFEST_VERSION,
+            key_id: key_id(),
+            files: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, relative: &Path, plaintext: &[u8], ciphertext: &[u8]) {
+        let entry_key_id = match ContainerHeader::parse(ciphertext) {
+            Some(header) => Some(bytes_to_hex(&header.key_id)),
+            None => is_encrypted(ciphertext).then(key_id),
+        };
+        self.files.push(ManifestEntry {
+            path: manifest_path(relative),
+            size: ciphertext.len() as u64,
+            key_id: entry_key_id,
+            plaintext_sha256: sha256_hex(plaintext),
+            ciphertext_sha256: sha256_hex(ciphertext),
+        });
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest> {
+        let manifest: Manifest = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if manifest.version != MANIFEST_VERSION {
+            anyhow::bail!("unsupported manifest version {}", manifest.version);
+        }
+        Ok(manifest)
+    }
+
+    /// 对比部署目录与清单：内容被修改、清单中的文件缺失、目录中多出未登记的 PHP 文件
+    pub fn audit(&self, dir: &Path) -> Result<Vec<AuditIssue>> {
+        let mut deployed = BTreeMap::new();
+        for entry in walkdir::WalkDir::new(dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "php"))
+        {
+            let relative = entry.path().strip_prefix(dir)?;
+            deployed.insert(manifest_path(relative), entry.into_path());
+        }
+
+        let mut issues = Vec::new();
+        for file in &self.files {
+            match deployed.remove(&file.path) {
+                Some(path) => {
+                    let content = fs::read(&path)?;
+                    if content.len() as u64 != file.size
+                        || sha256_hex(&content) != file.ciphertext_sha256
+                    {
+                        issues.push(AuditIssue::Modified(file.path.clone()));
+                    }
+                }
+                None => issues.push(AuditIssue::Missing(file.path.clone())),
+            }
+        }
+        issues.extend(deployed.into_keys().map(AuditIssue::Added));
+
+        Ok(issues)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use php_guard_core::encrypt_content;
+
+    #[test]
+    fn test_audit_detects_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = Manifest::new();
+        for name in ["a.php", "lib/b.php", "lib/c.php"] {
+            let plain = format!("<?php echo '{}';", name);
+            let encrypted = encrypt_content(plain.as_bytes()).unwrap();
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, &encrypted).unwrap();
+            manifest.add(Path::new(name), plain.as_bytes(), &encrypted);
+        }
+        assert!(manifest.audit(dir.path()).unwrap().is_empty());
+
+        fs::write(
+            dir.path().join("a.php"),
+            encrypt_content(b"<?php evil();").unwrap(),
+        )
+        .unwrap();
+        fs::remove_file(dir.path().join("lib/c.php")).unwrap();
+        fs::write(dir.path().join("lib/d.php"), b"<?php backdoor();").unwrap();
+
+        assert_eq!(
+            manifest.audit(dir.path()).unwrap(),
+            [
+                AuditIssue::Modified("a.php".to_string()),
+                AuditIssue::Missing("lib/c.php".to_string()),
+                AuditIssue::Added("lib/d.php".to_string()),
+            ]
+        );
+    }
+}
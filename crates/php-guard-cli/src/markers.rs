@@ -0,0 +1,75 @@
+//! 文件内的加密标记，写在任意 PHP 注释中:
+//!
+//! - `@php-guard-ignore`: 保持明文，不加密
+//! - `@php-guard-encrypt`: 在 `--marked-only` 模式下只加密带此标记的文件
+//!
+//! 标记只作用于整个文件，不支持片段级标记：加密容器整体解密后才交给 PHP 编译，
+//! 需要保持明文的片段应放到单独的文件中。
+
+use php_guard_core::lexer::{TokenKind, tokenize};
+
+const IGNORE: &[u8] = b"@php-guard-ignore";
+const ENCRYPT: &[u8] = b"@php-guard-encrypt";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Markers {
+    pub ignore: bool,
+    pub encrypt: bool,
+}
+
+impl Markers {
+    pub fn scan(src: &[u8]) -> Markers {
+        let mut markers = Markers::default();
+        // 绝大多数文件没有标记，先做一次廉价的子串判断
+        if !contains(src, b"@php-guard-") {
+            return markers;
+        }
+        for token in tokenize(src) {
+            if matches!(
+                token.kind,
+                TokenKind::LineComment | TokenKind::BlockComment | TokenKind::DocComment
+            ) {
+                markers.ignore |= contains_word(token.text, IGNORE);
+                markers.encrypt |= contains_word(token.text, ENCRYPT);
+            }
+        }
+        markers
+    }
+
+    pub fn should_encrypt(&self, marked_only: bool) -> bool {
+        !self.ignore && (self.encrypt || !marked_only)
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn contains_word(haystack: &[u8], word: &[u8]) -> bool {
+    haystack.windows(word.len()).enumerate().any(|(i, w)| {
+        w == word
+            && !haystack
+                .get(i + word.len())
+                .is_some_and(|&c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markers_in_comments_only() {
+        let ignored = Markers::scan(b"<?php\n// @php-guard-ignore\nreturn [];");
+        assert!(ignored.ignore);
+        assert!(!ignored.should_encrypt(false));
+
+        let opted_in = Markers::scan(b"<?php\n/** @php-guard-encrypt */\nclass A {}");
+        assert!(opted_in.should_encrypt(true));
+
+        let plain = Markers::scan(b"<?php\n$s = '@php-guard-ignore';\n# @php-guard-ignored");
+        assert_eq!(plain, Markers::default());
+        assert!(plain.should_encrypt(false));
+        assert!(!plain.should_encrypt(true));
+    }
+}
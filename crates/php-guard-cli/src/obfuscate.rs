@@ -0,0 +1,676 @@
+//! 标识符混淆：重命名函数内的局部变量，以及可选的私有方法和属性。
+//!
+//! 基于记号流做保守的重命名。局部变量以最外层函数为单位统一改名，嵌套的闭包和箭头函数
+//! 共用同一张映射表，因此 `use` 捕获和自动捕获的变量仍然对应。任何可能按名字访问变量的
+//! 写法 (`compact`、`extract`、`$$`、`eval`、`include` 等) 都会让整个函数保持原样；参数名
+//! 参与命名实参，`global` 声明的变量对应全局名，也都不改。
+//!
+//! 私有成员只有在类内所有访问都经由 `$this`、`self`、`static` 或类名，且名字没有出现在
+//! 任何字符串中时才会改名。改名会改变序列化数据和反射结果，需要显式开启。
+
+use std::collections::{HashMap, HashSet};
+
+use php_guard_core::lexer::{Token, TokenKind, is_ident_char, is_ident_start, tokenize};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ObfuscateOptions {
+    pub locals: bool,
+    pub private_members: bool,
+}
+
+const RESERVED_VARIABLES: &[&str] = &[
+    "this",
+    "GLOBALS",
+    "_SERVER",
+    "_GET",
+    "_POST",
+    "_FILES",
+    "_COOKIE",
+    "_SESSION",
+    "_REQUEST",
+    "_ENV",
+    "http_response_header",
+    "argc",
+    "argv",
+    "php_errormsg",
+];
+
+/// 出现即跳过整个函数：按名字读写局部变量，或让其他文件共享局部作用域
+const SCOPE_HAZARDS: &[&str] = &[
+    "compact",
+    "extract",
+    "get_defined_vars",
+    "parse_str",
+    "eval",
+    "include",
+    "include_once",
+    "require",
+    "require_once",
+];
+
+const MODIFIERS: &[&str] = &[
+    "public",
+    "protected",
+    "private",
+    "static",
+    "readonly",
+    "final",
+    "abstract",
+    "var",
+];
+
+/// 声明了这些方法的类可能按名字转发访问，不改其私有成员
+const MAGIC_ACCESSORS: &[&str] = &[
+    "__get",
+    "__set",
+    "__isset",
+    "__unset",
+    "__call",
+    "__callstatic",
+];
+
+pub fn obfuscate(src: &[u8], options: &ObfuscateOptions) -> Vec<u8> {
+    let mut source = Source::new(src);
+    if options.locals {
+        source.rename_locals();
+    }
+    if options.private_members {
+        source.rename_private_members();
+    }
+    source.render()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Member {
+    Method,
+    Property,
+}
+
+struct Function {
+    /// `function` / `fn` 关键字的位置，以下均为 `sig` 中的下标
+    start: usize,
+    params: (usize, usize),
+    body: Option<(usize, usize)>,
+}
+
+struct Source<'a> {
+    tokens: Vec<Token<'a>>,
+    /// 非空白、非注释记号在 `tokens` 中的下标
+    sig: Vec<usize>,
+    replacements: HashMap<usize, Vec<u8>>,
+}
+
+impl<'a> Source<'a> {
+    fn new(src: &'a [u8]) -> Source<'a> {
+        let tokens = tokenize(src);
+        let sig = (0..tokens.len())
+            .filter(|&i| !tokens[i].is_trivia())
+            .collect();
+        Source {
+            tokens,
+            sig,
+            replacements: HashMap::new(),
+        }
+    }
+
+    fn render(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (i, token) in self.tokens.iter().enumerate() {
+            match self.replacements.get(&i) {
+                Some(text) => out.extend_from_slice(text),
+                None => out.extend_from_slice(token.text),
+            }
+        }
+        out
+    }
+
+    fn tok(&self, i: usize) -> Option<&Token<'a>> {
+        self.sig.get(i).map(|&t| &self.tokens[t])
+    }
+
+    fn punct(&self, i: usize, c: u8) -> bool {
+        self.tok(i).is_some_and(|t| t.is_punct(c))
+    }
+
+    fn ident(&self, i: usize, name: &str) -> bool {
+        self.tok(i).is_some_and(|t| t.is_ident(name))
+    }
+
+    fn kind(&self, i: usize) -> Option<TokenKind> {
+        self.tok(i).map(|t| t.kind)
+    }
+
+    /// 由相邻单字节记号组成的运算符，如 `->`、`::`
+    fn op(&self, i: usize, op: &[u8]) -> bool {
+        op.iter().enumerate().all(|(k, &c)| {
+            self.punct(i + k, c) && (k == 0 || self.sig[i + k] == self.sig[i + k - 1] + 1)
+        })
+    }
+
+    fn after_member_op(&self, i: usize) -> bool {
+        i >= 2 && (self.op(i - 2, b"->") || self.op(i - 2, b"::"))
+    }
+
+    fn matching(&self, open: usize) -> Option<usize> {
+        let (o, c) = match self.tok(open)?.text {
+            b"(" => (b'(', b')'),
+            b"{" => (b'{', b'}'),
+            _ => return None,
+        };
+        let mut depth = 0usize;
+        for i in open..self.sig.len() {
+            if self.punct(i, o) {
+                depth += 1;
+            } else if self.punct(i, c) {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+        }
+        None
+    }
+
+    fn replace(&mut self, i: usize, text: Vec<u8>) {
+        self.replacements.insert(self.sig[i], text);
+    }
+
+    fn functions(&self) -> Vec<Function> {
+        let mut functions = Vec::new();
+        for i in 0..self.sig.len() {
+            let arrow = self.ident(i, "fn");
+            if !(arrow || self.ident(i, "function")) || self.after_member_op(i) {
+                continue;
+            }
+            let mut j = i + 1;
+            if self.punct(j, b'&') {
+                j += 1;
+            }
+            if self.kind(j) == Some(TokenKind::Ident) {
+                j += 1;
+            }
+            if !self.punct(j, b'(') {
+                continue;
+            }
+            let Some(params_end) = self.matching(j) else {
+                continue;
+            };
+
+            let mut body = None;
+            if !arrow {
+                let mut k = params_end + 1;
+                while k < self.sig.len() && !self.punct(k, b'{') && !self.punct(k, b';') {
+                    k = match self.punct(k, b'(') {
+                        true => self.matching(k).unwrap_or(self.sig.len()) + 1,
+                        false => k + 1,
+                    };
+                }
+                if self.punct(k, b'{') {
+                    body = self.matching(k).map(|end| (k, end));
+                }
+            }
+
+            functions.push(Function {
+                start: i,
+                params: (j, params_end),
+                body,
+            });
+        }
+        functions
+    }
+
+    fn rename_locals(&mut self) {
+        let functions = self.functions();
+        let mut covered = 0;
+        for function in &functions {
+            let Some((_, end)) = function.body else {
+                continue;
+            };
+            if function.start < covered {
+                continue;
+            }
+            covered = end;
+            self.rename_scope(function.start, end, &functions);
+        }
+    }
+
+    fn rename_scope(&mut self, start: usize, end: usize, functions: &[Function]) {
+        let mut excluded: HashSet<Vec<u8>> = RESERVED_VARIABLES
+            .iter()
+            .map(|name| name.as_bytes().to_vec())
+            .collect();
+        for function in functions
+            .iter()
+            .filter(|f| f.start >= start && f.start <= end)
+        {
+            for i in function.params.0..function.params.1 {
+                if self.kind(i) == Some(TokenKind::Variable) {
+                    excluded.insert(self.tok(i).unwrap().text[1..].to_vec());
+                }
+            }
+        }
+
+        let mut names = HashSet::new();
+        let mut variables = Vec::new();
+        let mut strings = Vec::new();
+        let mut in_global = false;
+
+        for i in start..=end {
+            let token = *self.tok(i).unwrap();
+            match token.kind {
+                TokenKind::Ident => {
+                    let lower = token.text.to_ascii_lowercase();
+                    if SCOPE_HAZARDS.iter().any(|h| h.as_bytes() == lower) {
+                        return;
+                    }
+                    // 匿名类的属性声明不是局部变量
+                    if token.is_ident("class") && !self.after_member_op(i) {
+                        return;
+                    }
+                    if token.is_ident("global") {
+                        in_global = true;
+                    }
+                }
+                TokenKind::Punct if token.text == b"$" => return,
+                TokenKind::Punct if token.text == b";" => in_global = false,
+                TokenKind::Variable => {
+                    // `self::$x` 是静态属性
+                    if i >= 2 && self.op(i - 2, b"::") {
+                        continue;
+                    }
+                    let name = &token.text[1..];
+                    if in_global {
+                        excluded.insert(name.to_vec());
+                    }
+                    names.insert(name.to_vec());
+                    variables.push(i);
+                }
+                TokenKind::InterpolatedString | TokenKind::Heredoc => {
+                    let Some(vars) = string_variables(token.text) else {
+                        return;
+                    };
+                    names.extend(vars.iter().map(|&(s, e)| token.text[s + 1..e].to_vec()));
+                    strings.push(i);
+                }
+                _ => {}
+            }
+        }
+
+        let mut candidates: Vec<&Vec<u8>> = names.difference(&excluded).collect();
+        candidates.sort();
+        let mut generator = NameGenerator::default();
+        let mapping: HashMap<Vec<u8>, Vec<u8>> = candidates
+            .into_iter()
+            .map(|name| {
+                let new = generator.next(|n| names.contains(n) || excluded.contains(n));
+                (name.clone(), new)
+            })
+            .collect();
+
+        for i in variables {
+            let text = self.tok(i).unwrap().text;
+            if let Some(new) = mapping.get(&text[1..]) {
+                self.replace(i, [b"$".as_slice(), new].concat());
+            }
+        }
+        for i in strings {
+            let text = self.tok(i).unwrap().text;
+            let mut out = Vec::with_capacity(text.len());
+            let mut last = 0;
+            for (s, e) in string_variables(text).unwrap_or_default() {
+                if let Some(new) = mapping.get(&text[s + 1..e]) {
+                    out.extend_from_slice(&text[last..s + 1]);
+                    out.extend_from_slice(new);
+                    last = e;
+                }
+            }
+            if last > 0 {
+                out.extend_from_slice(&text[last..]);
+                self.replace(i, out);
+            }
+        }
+    }
+
+    fn rename_private_members(&mut self) {
+        let mut string_words = HashSet::new();
+        for token in &self.tokens {
+            if matches!(
+                token.kind,
+                TokenKind::String
+                    | TokenKind::InterpolatedString
+                    | TokenKind::Heredoc
+                    | TokenKind::Nowdoc
+            ) {
+                string_words.extend(
+                    token
+                        .text
+                        .split(|&c| !is_ident_char(c))
+                        .filter(|w| !w.is_empty())
+                        .map(|w| w.to_ascii_lowercase()),
+                );
+            }
+        }
+
+        for i in 0..self.sig.len() {
+            if !self.ident(i, "class")
+                || self.after_member_op(i)
+                || (i > 0 && self.ident(i - 1, "new"))
+                || self.kind(i + 1) != Some(TokenKind::Ident)
+            {
+                continue;
+            }
+            let mut open = i + 2;
+            while open < self.sig.len() && !self.punct(open, b'{') {
+                open += 1;
+            }
+            if let Some(close) = self.matching(open) {
+                self.rename_class_members(i + 1, open, close, &string_words);
+            }
+        }
+    }
+
+    fn member_key(&self, member: Member, i: usize) -> Vec<u8> {
+        let text = self.tok(i).unwrap().text;
+        match member {
+            Member::Method => text.to_ascii_lowercase(),
+            Member::Property => text.strip_prefix(b"$").unwrap_or(text).to_vec(),
+        }
+    }
+
+    fn rename_class_members(
+        &mut self,
+        name: usize,
+        open: usize,
+        close: usize,
+        string_words: &HashSet<Vec<u8>>,
+    ) {
+        let class_name = self.tok(name).unwrap().text.to_ascii_lowercase();
+
+        // 声明: (类别, 名字, 位置)，以及其中的私有成员
+        let mut declarations = Vec::new();
+        let mut private = HashSet::new();
+        let mut depth = 0usize;
+        let mut k = open + 1;
+        while k < close {
+            if self.punct(k, b'{') {
+                depth += 1;
+            } else if self.punct(k, b'}') {
+                depth -= 1;
+            } else if depth == 0 {
+                // 引入 trait 后，trait 中的代码可能访问本类私有成员
+                if self.ident(k, "use") {
+                    return;
+                }
+                let mut m = k;
+                let mut is_private = false;
+                while MODIFIERS.iter().any(|modifier| self.ident(m, modifier)) {
+                    is_private |= self.ident(m, "private");
+                    m += 1;
+                }
+                if self.ident(m, "function") {
+                    let mut j = m + 1;
+                    if self.punct(j, b'&') {
+                        j += 1;
+                    }
+                    let key = self.member_key(Member::Method, j);
+                    if MAGIC_ACCESSORS.iter().any(|magic| magic.as_bytes() == key) {
+                        return;
+                    }
+                    if is_private {
+                        private.insert((Member::Method, key.clone()));
+                    }
+                    declarations.push((Member::Method, key, j));
+                    // 跳过参数列表，构造函数提升的属性不在这里处理
+                    k = match self.punct(j + 1, b'(') {
+                        true => self.matching(j + 1).unwrap_or(close),
+                        false => j,
+                    };
+                } else if m > k && !self.ident(m, "const") {
+                    while m < close && !self.punct(m, b';') {
+                        // 属性钩子 (PHP 8.4) 不处理
+                        if self.punct(m, b'{') {
+                            return;
+                        }
+                        if self.kind(m) == Some(TokenKind::Variable) {
+                            let key = self.member_key(Member::Property, m);
+                            if is_private {
+                                private.insert((Member::Property, key.clone()));
+                            }
+                            declarations.push((Member::Property, key, m));
+                        }
+                        m += 1;
+                    }
+                    k = m;
+                }
+            }
+            k += 1;
+        }
+
+        // 访问: (类别, 名字, 位置, 是否经由 $this/self/static/类名)
+        let mut uses = Vec::new();
+        for k in open + 1..close {
+            if self.op(k, b"->") {
+                let n = k + 2;
+                if self.kind(n) != Some(TokenKind::Ident) {
+                    // `$this->$name`、`$this->{...}` 无法静态分析
+                    return;
+                }
+                let receiver = match self.punct(k - 1, b'?') {
+                    true => k - 2,
+                    false => k - 1,
+                };
+                let via_this = self
+                    .tok(receiver)
+                    .is_some_and(|t| t.kind == TokenKind::Variable && t.text == b"$this");
+                let member = match self.punct(n + 1, b'(') {
+                    true => Member::Method,
+                    false => Member::Property,
+                };
+                uses.push((member, self.member_key(member, n), n, via_this));
+            } else if self.op(k, b"::") {
+                let n = k + 2;
+                let via_class = self.tok(k - 1).is_some_and(|t| {
+                    t.is_ident("self")
+                        || t.is_ident("static")
+                        || (t.kind == TokenKind::Ident && t.text.eq_ignore_ascii_case(&class_name))
+                });
+                let member = match self.kind(n) {
+                    Some(TokenKind::Ident) if self.punct(n + 1, b'(') => Member::Method,
+                    Some(TokenKind::Variable) => Member::Property,
+                    _ => continue,
+                };
+                uses.push((member, self.member_key(member, n), n, via_class));
+            }
+        }
+
+        let safe: HashSet<(Member, Vec<u8>)> = private
+            .into_iter()
+            .filter(|(member, key)| {
+                !key.starts_with(b"__")
+                    && !string_words.contains(&key.to_ascii_lowercase())
+                    && uses
+                        .iter()
+                        .filter(|(m, k, _, _)| m == member && k == key)
+                        .all(|&(_, _, _, ok)| ok)
+            })
+            .collect();
+        if safe.is_empty() {
+            return;
+        }
+
+        let mut taken: HashSet<Vec<u8>> = HashSet::new();
+        for i in open..=close {
+            let token = self.tok(i).unwrap();
+            match token.kind {
+                TokenKind::Ident => taken.insert(token.text.to_ascii_lowercase()),
+                TokenKind::Variable => taken.insert(token.text[1..].to_ascii_lowercase()),
+                _ => false,
+            };
+        }
+
+        let mut sorted: Vec<_> = safe.into_iter().collect();
+        sorted.sort();
+        let mut generator = NameGenerator::default();
+        let mapping: HashMap<(Member, Vec<u8>), Vec<u8>> = sorted
+            .into_iter()
+            .map(|member| {
+                let new = generator.next(|n| taken.contains(n));
+                (member, new)
+            })
+            .collect();
+
+        let occurrences = declarations
+            .into_iter()
+            .chain(uses.into_iter().map(|(member, key, i, _)| (member, key, i)));
+        let mut replacements = Vec::new();
+        for (member, key, i) in occurrences {
+            if let Some(new) = mapping.get(&(member, key)) {
+                let prefix: &[u8] = match self.kind(i) {
+                    Some(TokenKind::Variable) => b"$",
+                    _ => b"",
+                };
+                replacements.push((i, [prefix, new].concat()));
+            }
+        }
+        for (i, text) in replacements {
+            self.replace(i, text);
+        }
+    }
+}
+
+/// 双引号字符串和 heredoc 中插值变量 `$name` 的位置 (含 `$`)。
+/// 出现 `${` 时返回 `None`：`"${expr}"` 可以按表达式的值取变量。
+fn string_variables(text: &[u8]) -> Option<Vec<(usize, usize)>> {
+    let mut vars = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        match text[i] {
+            b'\\' => i += 2,
+            b'$' if text.get(i + 1) == Some(&b'{') => return None,
+            b'$' if text.get(i + 1).copied().is_some_and(is_ident_start) => {
+                let mut end = i + 1;
+                while end < text.len() && is_ident_char(text[end]) {
+                    end += 1;
+                }
+                vars.push((i, end));
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    Some(vars)
+}
+
+/// 依次生成 a, b, ..., z, aa, ab, ...，跳过已被占用的名字
+#[derive(Default)]
+struct NameGenerator {
+    next: usize,
+}
+
+impl NameGenerator {
+    fn next(&mut self, taken: impl Fn(&[u8]) -> bool) -> Vec<u8> {
+        loop {
+            let mut n = self.next;
+            self.next += 1;
+            let mut name = Vec::new();
+            loop {
+                name.push(b'a' + (n % 26) as u8);
+                n /= 26;
+                if n == 0 {
+                    break;
+                }
+                n -= 1;
+            }
+            name.reverse();
+            if !taken(&name) {
+                return name;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(src: &str, locals: bool, private_members: bool) -> String {
+        let options = ObfuscateOptions {
+            locals,
+            private_members,
+        };
+        String::from_utf8(obfuscate(src.as_bytes(), &options)).unwrap()
+    }
+
+    #[test]
+    fn test_rename_locals() {
+        let src = r#"<?php
+$top = 1;
+function total(array $items, $rate) {
+    global $config;
+    $sum = 0;
+    foreach ($items as $item) { $sum += $item; }
+    $fn = function ($x) use ($sum) { return $x + $sum; };
+    $double = fn($y) => $y * $sum;
+    return "total: $sum {$config['unit']}" . self::$cache . $fn($rate) . $double(2);
+}
+"#;
+        let out = run(src, true, false);
+        assert!(out.contains("$top = 1;"));
+        assert!(out.contains("function total(array $items, $rate)"));
+        assert!(out.contains("global $config;"));
+        assert!(out.contains("self::$cache"));
+        assert!(!out.contains("$sum"));
+        assert!(!out.contains("$item;"));
+        assert!(!out.contains("$fn"));
+        assert!(!out.contains("$double"));
+        // 新名字按原名排序依次分配 (double, fn, item, sum -> a, b, c, d)；
+        // 闭包参数保持原名，捕获的变量与外层一致
+        assert!(out.contains("function ($x) use ($d) { return $x + $d; }"));
+        assert!(out.contains("\"total: $d {$config['unit']}\""));
+    }
+
+    #[test]
+    fn test_hazards_skip_function() {
+        for body in [
+            "return compact('a');",
+            "extract($a);",
+            "$name = 'a'; return $$name;",
+            "include 'x.php';",
+            "return \"${a}\";",
+        ] {
+            let src = format!("<?php function f() {{ $a = 1; {} }}", body);
+            assert_eq!(run(&src, true, false), src);
+        }
+    }
+
+    #[test]
+    fn test_rename_private_members() {
+        let src = r#"<?php
+class Counter {
+    private int $count = 0;
+    private static $instances = 0;
+    private $label;
+    public function __construct(private string $name) { self::$instances++; }
+    public function add() { $this->count = $this->step($this->count); return $this; }
+    private function step($n) { return $n + 1; }
+    private function helper() {}
+    public function same(Counter $other) { return $other->label === $this->label; }
+    public function callback() { return [$this, 'helper']; }
+}
+"#;
+        let out = run(src, false, true);
+        assert!(!out.contains("count"));
+        assert!(!out.contains("instances"));
+        assert!(!out.contains("step"));
+        // 经由其他实例访问、出现在字符串中、构造函数提升的成员保持原名
+        assert!(out.contains("$other->label === $this->label"));
+        assert!(out.contains("private function helper()"));
+        assert!(out.contains("private string $name"));
+    }
+
+    #[test]
+    fn test_magic_accessors_skip_class() {
+        let src = "<?php class A { private $x; public function __get($n) { return $this->$n; } }";
+        assert_eq!(run(src, false, true), src);
+    }
+}
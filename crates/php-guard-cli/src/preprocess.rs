@@ -0,0 +1,64 @@
+use clap::Args;
+
+use php_guard_core::StripOptions;
+use php_guard_core::codec::EncryptorBuilder;
+
+use crate::obfuscate::{ObfuscateOptions, obfuscate};
+
+/// 加密前对源码的处理，`encrypt` 与 `watch` 共用
+#[derive(Debug, Clone, Default, Args)]
+pub struct PreprocessOptions {
+    #[arg(
+        long,
+        help = "Remove comments and insignificant whitespace before encrypting"
+    )]
+    pub strip: bool,
+    #[arg(long, requires = "strip", help = "Keep line numbers when stripping")]
+    pub keep_lines: bool,
+    #[arg(
+        long,
+        requires = "strip",
+        help = "Keep docblocks containing @annotations when stripping"
+    )]
+    pub keep_annotations: bool,
+    #[arg(long, help = "Rename local variables inside functions")]
+    pub obfuscate: bool,
+    #[arg(
+        long,
+        requires = "obfuscate",
+        help = "Also rename private methods and properties (changes serialized objects and reflection)"
+    )]
+    pub obfuscate_private: bool,
+}
+
+impl PreprocessOptions {
+    /// 把处理步骤加入加密器：先混淆再去除注释空白
+    pub fn configure(&self, mut builder: EncryptorBuilder) -> EncryptorBuilder {
+        if self.obfuscate {
+            let options = ObfuscateOptions {
+                locals: true,
+                private_members: self.obfuscate_private,
+            };
+            builder = builder.preprocess(move |source| obfuscate(source, &options));
+        }
+        if self.strip {
+            builder = builder.strip(StripOptions {
+                keep_lines: self.keep_lines,
+                keep_annotations: self.keep_annotations,
+            });
+        }
+        builder
+    }
+
+    /// 写入构建缓存的选项描述
+    pub fn cache_key(&self) -> String {
+        format!(
+            "strip={}{}{};obfuscate={}{}",
+            self.strip as u8,
+            self.keep_lines as u8,
+            self.keep_annotations as u8,
+            self.obfuscate as u8,
+            self.obfuscate_private as u8
+        )
+    }
+}
@@ -0,0 +1,107 @@
+//! 解密性能：legacy 逐字节算法与 ChaCha20 对比。
+//!
+//! 默认使用生成的框架式源码树；设置 `PHP_GUARD_BENCH_DIR` 可改用真实项目目录：
+//!
+//!     PHP_GUARD_BENCH_DIR=/path/to/laravel cargo bench -p php-guard-core
+
+use std::fs;
+use std::path::Path;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use php_guard_core::crypto::{chacha20_with, decode_with};
+use php_guard_core::{Algorithm, Decryptor, Encryptor, key};
+
+/// 模拟框架源码：大量几 KB 的类文件加少数几百 KB 的大文件
+fn synthetic_tree() -> Vec<Vec<u8>> {
+    let class = |n: usize, methods: usize| {
+        let mut source = format!(
+            "<?php\n\nnamespace App\\Generated;\n\nclass Service{}\n{{\n",
+            n
+        );
+        for m in 0..methods {
+            source.push_str(&format!(
+                "    /**\n     * Handle request {m}.\n     */\n    public function handle{m}(array $input): array\n    {{\n        $result = [];\n        foreach ($input as $key => $value) {{\n            $result[$key] = strtoupper((string) $value) . '-{n}-{m}';\n        }}\n        return $result;\n    }}\n\n"
+            ));
+        }
+        source.push_str("}\n");
+        source.into_bytes()
+    };
+    let mut tree: Vec<Vec<u8>> = (0..400).map(|n| class(n, 4 + n % 12)).collect();
+    tree.extend((0..4).map(|n| class(1000 + n, 800)));
+    tree
+}
+
+fn collect_php(dir: &Path, tree: &mut Vec<Vec<u8>>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_php(&path, tree);
+        } else if path.extension().is_some_and(|ext| ext == "php")
+            && let Ok(content) = fs::read(&path)
+        {
+            tree.push(content);
+        }
+    }
+}
+
+fn load_tree() -> Vec<Vec<u8>> {
+    match std::env::var("PHP_GUARD_BENCH_DIR") {
+        Ok(dir) => {
+            let mut tree = Vec::new();
+            collect_php(Path::new(&dir), &mut tree);
+            assert!(!tree.is_empty(), "no .php files under {}", dir);
+            tree
+        }
+        Err(_) => synthetic_tree(),
+    }
+}
+
+/// 只比较算法本身
+fn bench_raw(c: &mut Criterion) {
+    let key = key();
+    let mut group = c.benchmark_group("raw");
+    for size in [4 * 1024, 64 * 1024, 1024 * 1024] {
+        let data = vec![0x5au8; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("xor", size), &data, |b, data| {
+            let mut buf = data.clone();
+            b.iter(|| decode_with(&key, &mut buf));
+        });
+        group.bench_with_input(BenchmarkId::new("chacha20", size), &data, |b, data| {
+            let mut buf = data.clone();
+            b.iter(|| chacha20_with(&key, &[0; 12], &mut buf));
+        });
+    }
+    group.finish();
+}
+
+/// 冷启动时解密整个源码树，包括容器解析和明文缓冲区的分配与清零
+fn bench_tree(c: &mut Criterion) {
+    let tree = load_tree();
+    let total: usize = tree.iter().map(Vec::len).sum();
+    let decryptor = Decryptor::default();
+
+    let mut group = c.benchmark_group("tree");
+    group.throughput(Throughput::Bytes(total as u64));
+    for algorithm in [Algorithm::Xor, Algorithm::ChaCha20] {
+        let encryptor = Encryptor::builder().algorithm(algorithm).build().unwrap();
+        let encrypted: Vec<Vec<u8>> = tree
+            .iter()
+            .map(|source| encryptor.encrypt(source).unwrap())
+            .collect();
+        group.bench_function(algorithm.name(), |b| {
+            b.iter(|| {
+                for file in &encrypted {
+                    decryptor.decrypt(file).unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_raw, bench_tree);
+criterion_main!(benches);
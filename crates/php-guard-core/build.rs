@@ -20,27 +20,50 @@ fn main() {
     });
     let config_file = Path::new(&config_dir).join("config.env");
 
-    let (key, header) = if config_file.exists() {
+    let config = if config_file.exists() {
         read_config_from_file(&config_file)
     } else {
         let key = generate_random_bytes(32);
         let header = generate_random_bytes(16);
         save_config_to_file(&config_file, &key, &header);
-        (key, header)
+        Config {
+            key,
+            header,
+            require_license: false,
+            sign_public_key: None,
+            debugger_policy: "Allow",
+        }
     };
 
-    let code = generate_config_code(&key, &header);
+    let code = generate_config_code(&config);
     fs::write(&dest_path, code).unwrap();
 
+    // 测试用它核对还原出的密钥
+    println!(
+        "cargo:rustc-env=PHP_GUARD_CONFIG_FILE={}",
+        config_file.display()
+    );
     println!("cargo:rerun-if-changed={}", config_file.display());
     println!("cargo:rerun-if-env-changed=PHP_GUARD_CONFIG_DIR");
 }
 
-fn read_config_from_file(path: &Path) -> (Vec<u8>, Vec<u8>) {
+struct Config {
+    key: Vec<u8>,
+    header: Vec<u8>,
+    require_license: bool,
+    sign_public_key: Option<Vec<u8>>,
+    /// `DebuggerPolicy` 的变体名
+    debugger_policy: &'static str,
+}
+
+fn read_config_from_file(path: &Path) -> Config {
     let content = fs::read_to_string(path).expect("Failed to read config file");
 
     let mut key = None;
     let mut header = None;
+    let mut require_license = false;
+    let mut sign_public_key = None;
+    let mut debugger_policy = "Allow";
 
     for line in content.lines() {
         let line = line.trim();
@@ -53,13 +76,48 @@ fn read_config_from_file(path: &Path) -> (Vec<u8>, Vec<u8>) {
         {
             let value = line.split('=').nth(1).unwrap().trim().trim_matches('"');
             header = Some(hex_to_bytes(value));
+        } else if line.starts_with("export PHP_GUARD_REQUIRE_LICENSE=")
+            || line.starts_with("PHP_GUARD_REQUIRE_LICENSE=")
+        {
+            let value = line.split('=').nth(1).unwrap().trim().trim_matches('"');
+            require_license = matches!(value, "1" | "true" | "yes");
+        } else if line.starts_with("export PHP_GUARD_SIGN_PUBKEY=")
+            || line.starts_with("PHP_GUARD_SIGN_PUBKEY=")
+        {
+            let value = line.split('=').nth(1).unwrap().trim().trim_matches('"');
+            let bytes = hex_to_bytes(value);
+            assert_eq!(bytes.len(), 32, "PHP_GUARD_SIGN_PUBKEY must be 32 bytes");
+            sign_public_key = Some(bytes);
+        } else if line.starts_with("export PHP_GUARD_DEBUGGER_POLICY=")
+            || line.starts_with("PHP_GUARD_DEBUGGER_POLICY=")
+        {
+            let value = line.split('=').nth(1).unwrap().trim().trim_matches('"');
+            debugger_policy = match value {
+                "allow" => "Allow",
+                "warn" => "Warn",
+                "refuse" => "Refuse",
+                "dev-license" => "DevLicense",
+                other => panic!(
+                    "PHP_GUARD_DEBUGGER_POLICY must be allow, warn, refuse or dev-license, got '{}'",
+                    other
+                ),
+            };
         }
     }
 
-    (
-        key.expect("PHP_GUARD_KEY not found"),
-        header.expect("PHP_GUARD_HEADER not found"),
-    )
+    // 授权用 Ed25519 签名，没有公钥时扩展无法校验任何授权
+    assert!(
+        !require_license || sign_public_key.is_some(),
+        "PHP_GUARD_REQUIRE_LICENSE requires PHP_GUARD_SIGN_PUBKEY to verify license signatures"
+    );
+
+    Config {
+        key: key.expect("PHP_GUARD_KEY not found"),
+        header: header.expect("PHP_GUARD_HEADER not found"),
+        require_license,
+        sign_public_key,
+        debugger_policy,
+    }
 }
 
 fn save_config_to_file(path: &Path, key: &[u8], header: &[u8]) {
@@ -109,14 +167,169 @@ fn bytes_to_hex(bytes: &[u8]) -> String {
     bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
-fn generate_config_code(key: &[u8], header: &[u8]) -> String {
+fn generate_config_code(config: &Config) -> String {
+    let sign_public_key = match &config.sign_public_key {
+        Some(key) => format!("Some([{}])", format_bytes_for_rust(key)),
+        None => "None".to_string(),
+    };
     format!(
-        "pub const KEY: &[u8] = &[{}];\npub const HEADER: &[u8] = &[{}];\n",
-        format_bytes_for_rust(key),
-        format_bytes_for_rust(header)
+        "pub const HEADER: &[u8] = &[{}];\npub const REQUIRE_LICENSE: bool = {};\npub const SIGN_PUBLIC_KEY: Option<[u8; 32]> = {};\npub const DEBUGGER_POLICY: crate::debugger::DebuggerPolicy = crate::debugger::DebuggerPolicy::{};\n{}",
+        format_bytes_for_rust(&config.header),
+        config.require_license,
+        sign_public_key,
+        config.debugger_policy,
+        generate_key_code(&config.key)
     )
 }
 
+/// 构建期使用的伪随机数发生器 (splitmix64)，只用于打散密钥的存放方式
+struct Rng(u64);
+
+impl Rng {
+    fn new(key: &[u8]) -> Rng {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let mut seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64
+            ^ (std::process::id() as u64) << 32;
+        for &b in key {
+            seed = seed.rotate_left(8) ^ b as u64;
+        }
+        Rng(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// 每段密钥的掩码方式：(存放时的变换, 还原表达式)，表达式中 `d` 为存放值、`m` 为掩码
+enum MaskOp {
+    Xor,
+    Add,
+    Sub,
+    RotateXor(u32),
+}
+
+impl MaskOp {
+    fn random(rng: &mut Rng) -> MaskOp {
+        match rng.below(4) {
+            0 => MaskOp::Xor,
+            1 => MaskOp::Add,
+            2 => MaskOp::Sub,
+            _ => MaskOp::RotateXor(1 + rng.below(7) as u32),
+        }
+    }
+
+    fn apply(&self, key: u8, mask: u8) -> u8 {
+        match self {
+            MaskOp::Xor => key ^ mask,
+            MaskOp::Add => key.wrapping_add(mask),
+            MaskOp::Sub => key.wrapping_sub(mask),
+            MaskOp::RotateXor(r) => (key ^ mask).rotate_left(*r),
+        }
+    }
+
+    fn restore(&self) -> String {
+        match self {
+            MaskOp::Xor => "d ^ m".to_string(),
+            MaskOp::Add => "d.wrapping_sub(m)".to_string(),
+            MaskOp::Sub => "d.wrapping_add(m)".to_string(),
+            MaskOp::RotateXor(r) => format!("d.rotate_right({}) ^ m", r),
+        }
+    }
+}
+
+/// 生成还原密钥的代码。
+///
+/// 密钥字节打乱顺序后分成若干段，每段用独立的 LCG 掩码流和随机选择的变换存放，
+/// 由各自的函数按随机顺序写回。段数、顺序、掩码参数和变换每次构建都不同，
+/// 二进制中不存在连续的明文密钥；`black_box` 防止编译器在编译期算出密钥。
+fn generate_key_code(key: &[u8]) -> String {
+    // 生成的代码用 u8 存放字节位置
+    assert!(
+        key.len() <= 256,
+        "PHP_GUARD_KEY 最长 256 字节，当前为 {} 字节",
+        key.len()
+    );
+    let mut rng = Rng::new(key);
+
+    let mut order: Vec<usize> = (0..key.len()).collect();
+    for i in (1..order.len()).rev() {
+        order.swap(i, rng.below(i + 1));
+    }
+    let parts = (4 + rng.below(5)).min(key.len().max(1));
+
+    let mut code = format!("const KEY_LEN: usize = {};\n", key.len());
+    let mut calls: Vec<String> = Vec::new();
+    let chunk = key.len().div_ceil(parts);
+    for (n, slots) in order.chunks(chunk.max(1)).enumerate() {
+        let multiplier = (rng.next() as u32) | 1;
+        let increment = rng.next() as u32;
+        let mut state = rng.next() as u32;
+        let shift = 8 + rng.below(17);
+        let op = MaskOp::random(&mut rng);
+
+        let data: Vec<u8> = slots
+            .iter()
+            .map(|&slot| {
+                state = state.wrapping_mul(multiplier).wrapping_add(increment);
+                op.apply(key[slot], (state >> shift) as u8)
+            })
+            .collect();
+        let slots: Vec<u8> = slots.iter().map(|&slot| slot as u8).collect();
+
+        code.push_str(&format!(
+            "\n#[inline(never)]\nfn key_part_{n}(out: &mut [u8]) {{\n    let data: [u8; {len}] = std::hint::black_box([{data}]);\n    let slots: [u8; {len}] = std::hint::black_box([{slots}]);\n    let mut state: u32 = std::hint::black_box(0x{state:08x});\n    for (&d, &slot) in data.iter().zip(slots.iter()) {{\n        state = state.wrapping_mul(0x{multiplier:08x}).wrapping_add(0x{increment:08x});\n        let m = (state >> {shift}) as u8;\n        out[slot as usize] = {restore};\n    }}\n}}\n",
+            len = data.len(),
+            data = format_bytes_for_rust(&data),
+            slots = format_bytes_for_rust(&slots),
+            state = state_before(&data, multiplier, increment, state),
+            restore = op.restore(),
+        ));
+        calls.push(format!("    key_part_{}(out);\n", n));
+    }
+
+    for i in (1..calls.len()).rev() {
+        calls.swap(i, rng.below(i + 1));
+    }
+    code.push_str("\nfn assemble_key(out: &mut [u8]) {\n");
+    for call in calls {
+        code.push_str(&call);
+    }
+    code.push_str("}\n");
+    code
+}
+
+/// `state` 已经前进了 `data.len()` 步，倒推出初始值写入生成的代码
+fn state_before(data: &[u8], multiplier: u32, increment: u32, state: u32) -> u32 {
+    let inverse = mod_inverse(multiplier);
+    let mut state = state;
+    for _ in data {
+        state = state.wrapping_sub(increment).wrapping_mul(inverse);
+    }
+    state
+}
+
+/// 奇数在模 2^32 下的乘法逆元 (牛顿迭代)
+fn mod_inverse(a: u32) -> u32 {
+    let mut x = a;
+    for _ in 0..5 {
+        x = x.wrapping_mul(2u32.wrapping_sub(a.wrapping_mul(x)));
+    }
+    x
+}
+
 fn format_bytes_for_rust(bytes: &[u8]) -> String {
     bytes
         .iter()
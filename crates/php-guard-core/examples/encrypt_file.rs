@@ -19,7 +19,13 @@ fn main() {
                 return;
             }
 
-            let encrypted = encrypt_content(&content);
+            let encrypted = match encrypt_content(&content) {
+                Ok(encrypted) => encrypted,
+                Err(e) => {
+                    eprintln!("加密失败: {}", e);
+                    std::process::exit(1);
+                }
+            };
 
             let output_path = format!("{}.encrypted", filepath);
             match std::fs::write(&output_path, &encrypted) {
@@ -0,0 +1,408 @@
+//! 分块容器布局 ([`FLAG_CHUNKED`](crate::format::FLAG_CHUNKED))。
+//!
+//! ```text
+//! payload = chunk_size(u32 LE) | chunk_0 | tag_0 | chunk_1 | tag_1 | ... | chunk_n | tag_n
+//! ```
+//!
+//! 每块用独立的 ChaCha20 密钥流加密，由密钥、容器头中的 nonce 和块序号决定 (最后一块可以更短，
+//! 空源码也有一块)。标签是
+//! HMAC-SHA256 的前 16 字节，覆盖容器头 (HEADER 到 nonce)、块序号、是否最后一块和
+//! 密文，块不能被替换、重排或截断。块之间互不依赖，可以并行校验和解密，也可以边读边解密，
+//! 出错时报告具体的块。
+
+use std::io::{self, Read};
+use std::thread;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::codec::Key;
+use crate::crypto::{NONCE_LENGTH, chacha20_at, chacha20_key};
+use crate::error::{Error, Result};
+use crate::secret::ct_eq;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const CHUNK_TAG_LENGTH: usize = 16;
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+pub const MIN_CHUNK_SIZE: u32 = 1024;
+
+/// 块数少于此值时单线程解密，启动线程的开销不划算
+const PARALLEL_MIN_CHUNKS: usize = 4;
+
+/// 块大小必须是 8 的倍数且不小于 [`MIN_CHUNK_SIZE`]
+pub fn validate_chunk_size(chunk_size: u32) -> Result<()> {
+    if chunk_size < MIN_CHUNK_SIZE || !chunk_size.is_multiple_of(8) {
+        return Err(Error::Config(format!(
+            "chunk size must be a multiple of 8 and at least {} bytes, got {}",
+            MIN_CHUNK_SIZE, chunk_size
+        )));
+    }
+    Ok(())
+}
+
+/// 一个文件的加密和认证密钥，每个文件只派生一次，各块共用
+struct FileKeys {
+    cipher_key: Zeroizing<[u8; 32]>,
+    mac_key: Zeroizing<[u8; 32]>,
+    nonce: [u8; NONCE_LENGTH],
+}
+
+impl FileKeys {
+    fn derive(key: &Key, nonce: [u8; NONCE_LENGTH]) -> FileKeys {
+        let mut hasher = Sha256::new();
+        hasher.update(b"php-guard chunk mac");
+        hasher.update(key.as_bytes());
+        FileKeys {
+            cipher_key: chacha20_key(key.as_bytes()),
+            mac_key: Zeroizing::new(hasher.finalize().into()),
+            nonce,
+        }
+    }
+
+    /// 第 `index` 块的 nonce：文件 nonce 的后 8 字节异或块序号，每块的密钥流从计数器 0 开始
+    fn chunk_nonce(&self, index: usize) -> [u8; NONCE_LENGTH] {
+        let mut nonce = self.nonce;
+        for (byte, i) in nonce[4..].iter_mut().zip((index as u64).to_le_bytes()) {
+            *byte ^= i;
+        }
+        nonce
+    }
+
+    fn apply(&self, index: usize, data: &mut [u8]) {
+        chacha20_at(&self.cipher_key, &self.chunk_nonce(index), 0, data);
+    }
+
+    fn tag(&self, prefix: &[u8], index: usize, last: bool, cipher: &[u8]) -> [u8; 16] {
+        tag(&self.mac_key, prefix, index, last, cipher)
+    }
+}
+
+fn tag(mac_key: &[u8; 32], prefix: &[u8], index: usize, last: bool, cipher: &[u8]) -> [u8; 16] {
+    let mut mac = HmacSha256::new_from_slice(mac_key).expect("HMAC accepts any key length");
+    mac.update(prefix);
+    mac.update(&(index as u64).to_le_bytes());
+    mac.update(&[last as u8]);
+    mac.update(cipher);
+    let mut tag = [0u8; CHUNK_TAG_LENGTH];
+    tag.copy_from_slice(&mac.finalize().into_bytes()[..CHUNK_TAG_LENGTH]);
+    tag
+}
+
+/// 分块加密 `plaintext`，追加到 `out`；`prefix` 是 payload 之前的容器头
+pub(crate) fn seal(
+    key: &Key,
+    nonce: &[u8; NONCE_LENGTH],
+    prefix: &[u8],
+    plaintext: &[u8],
+    chunk_size: u32,
+    out: &mut Vec<u8>,
+) {
+    let keys = FileKeys::derive(key, *nonce);
+    let size = chunk_size as usize;
+    let count = plaintext.len().div_ceil(size).max(1);
+    out.reserve(4 + plaintext.len() + count * CHUNK_TAG_LENGTH);
+    out.extend_from_slice(&chunk_size.to_le_bytes());
+    for index in 0..count {
+        let offset = index * size;
+        let chunk = &plaintext[offset..plaintext.len().min(offset + size)];
+        let start = out.len();
+        out.extend_from_slice(chunk);
+        keys.apply(index, &mut out[start..]);
+        let tag = keys.tag(prefix, index, index + 1 == count, &out[start..]);
+        out.extend_from_slice(&tag);
+    }
+}
+
+/// 解析后的分块 payload
+pub(crate) struct Chunks<'a> {
+    prefix: &'a [u8],
+    nonce: [u8; NONCE_LENGTH],
+    chunk_size: usize,
+    body: &'a [u8],
+    count: usize,
+}
+
+impl<'a> Chunks<'a> {
+    pub(crate) fn parse(
+        prefix: &'a [u8],
+        nonce: [u8; NONCE_LENGTH],
+        payload: &'a [u8],
+    ) -> Result<Chunks<'a>> {
+        if payload.len() < 4 + CHUNK_TAG_LENGTH {
+            return Err(Error::Truncated);
+        }
+        let chunk_size = u32::from_le_bytes(payload[..4].try_into().unwrap());
+        validate_chunk_size(chunk_size)
+            .map_err(|_| Error::Corrupt(format!("invalid chunk size {}", chunk_size)))?;
+
+        let body = &payload[4..];
+        let stride = chunk_size as usize + CHUNK_TAG_LENGTH;
+        let count = body.len().div_ceil(stride);
+        if body.len() - (count - 1) * stride < CHUNK_TAG_LENGTH {
+            return Err(Error::Truncated);
+        }
+        Ok(Chunks {
+            prefix,
+            nonce,
+            chunk_size: chunk_size as usize,
+            body,
+            count,
+        })
+    }
+
+    pub(crate) fn plaintext_len(&self) -> usize {
+        self.body.len() - self.count * CHUNK_TAG_LENGTH
+    }
+
+    fn chunk(&self, index: usize) -> (&'a [u8], &'a [u8]) {
+        let start = index * (self.chunk_size + CHUNK_TAG_LENGTH);
+        let end = self
+            .body
+            .len()
+            .min(start + self.chunk_size + CHUNK_TAG_LENGTH);
+        self.body[start..end].split_at(end - start - CHUNK_TAG_LENGTH)
+    }
+
+    fn plain_len(&self, index: usize) -> usize {
+        self.chunk(index).0.len()
+    }
+
+    fn keys(&self, key: &Key) -> FileKeys {
+        FileKeys::derive(key, self.nonce)
+    }
+
+    fn verify(&self, keys: &FileKeys, index: usize) -> bool {
+        let (cipher, expected) = self.chunk(index);
+        let last = index + 1 == self.count;
+        ct_eq(&keys.tag(self.prefix, index, last, cipher), expected)
+    }
+
+    /// 校验第 `index` 块并解密到 `out`，`out` 的长度必须等于该块的明文长度
+    fn open(&self, keys: &FileKeys, index: usize, out: &mut [u8]) -> Result<()> {
+        if !self.verify(keys, index) {
+            return Err(Error::ChunkAuthenticationFailed(index));
+        }
+        out.copy_from_slice(self.chunk(index).0);
+        keys.apply(index, out);
+        Ok(())
+    }
+
+    /// 只校验标签不解密，返回第一个失败的块
+    pub(crate) fn verify_all(&self, key: &Key) -> Result<()> {
+        let keys = self.keys(key);
+        match (0..self.count).find(|&index| !self.verify(&keys, index)) {
+            Some(index) => Err(Error::ChunkAuthenticationFailed(index)),
+            None => Ok(()),
+        }
+    }
+
+    /// 解密全部块到 `out` (长度为 [`Chunks::plaintext_len`])，块较多时分给多个线程。
+    ///
+    /// 多个块校验失败时报告序号最小的一块。
+    pub(crate) fn open_all(&self, key: &Key, out: &mut [u8]) -> Result<()> {
+        let keys = self.keys(key);
+        let mut slices: Vec<(usize, &mut [u8])> = Vec::with_capacity(self.count);
+        let mut rest = out;
+        for index in 0..self.count {
+            let (head, tail) = rest.split_at_mut(self.plain_len(index));
+            slices.push((index, head));
+            rest = tail;
+        }
+
+        let threads = thread::available_parallelism()
+            .map_or(1, usize::from)
+            .min(self.count);
+        if threads <= 1 || self.count < PARALLEL_MIN_CHUNKS {
+            return slices
+                .into_iter()
+                .try_for_each(|(index, slice)| self.open(&keys, index, slice));
+        }
+
+        let keys = &keys;
+        let failed = thread::scope(|scope| {
+            let handles: Vec<_> = slices
+                .chunks_mut(self.count.div_ceil(threads))
+                .map(|group| {
+                    scope.spawn(move || {
+                        group.iter_mut().find_map(|(index, slice)| {
+                            self.open(keys, *index, slice).err().map(|_| *index)
+                        })
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .filter_map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .min()
+        });
+        match failed {
+            Some(index) => Err(Error::ChunkAuthenticationFailed(index)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// 逐块校验并解密的 reader，内存中最多保留一块明文 (drop 时清零)。
+///
+/// 校验失败时 `read` 返回的 `io::Error` 包装了 [`Error::ChunkAuthenticationFailed`]。
+pub(crate) struct ChunkReader<'a> {
+    chunks: Chunks<'a>,
+    keys: FileKeys,
+    next: usize,
+    buffer: Zeroizing<Vec<u8>>,
+    pos: usize,
+}
+
+impl<'a> ChunkReader<'a> {
+    pub(crate) fn new(chunks: Chunks<'a>, key: &Key) -> ChunkReader<'a> {
+        ChunkReader {
+            keys: chunks.keys(key),
+            buffer: Zeroizing::new(Vec::with_capacity(chunks.chunk_size)),
+            chunks,
+            next: 0,
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChunkReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buffer.len() {
+            if self.next == self.chunks.count {
+                return Ok(0);
+            }
+            // 容量等于块大小，resize 不会重新分配
+            self.buffer.clear();
+            self.buffer.resize(self.chunks.plain_len(self.next), 0);
+            self.chunks
+                .open(&self.keys, self.next, &mut self.buffer)
+                .map_err(io::Error::other)?;
+            self.next += 1;
+            self.pos = 0;
+        }
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFIX: &[u8] = b"header";
+    const NONCE: [u8; NONCE_LENGTH] = [7; NONCE_LENGTH];
+
+    fn sealed(plaintext: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        seal(
+            &Key::build(),
+            &NONCE,
+            PREFIX,
+            plaintext,
+            MIN_CHUNK_SIZE,
+            &mut out,
+        );
+        out
+    }
+
+    #[test]
+    fn test_open_all() {
+        let key = Key::build();
+        for len in [0usize, 1, 1024, 1025, 10 * 1024 + 7] {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let payload = sealed(&plaintext);
+            let chunks = Chunks::parse(PREFIX, NONCE, &payload).unwrap();
+            assert_eq!(chunks.count, len.div_ceil(1024).max(1));
+            assert_eq!(chunks.plaintext_len(), len);
+
+            let mut out = vec![0u8; len];
+            chunks.open_all(&key, &mut out).unwrap();
+            assert_eq!(out, plaintext);
+
+            let mut streamed = Vec::new();
+            ChunkReader::new(chunks, &key)
+                .read_to_end(&mut streamed)
+                .unwrap();
+            assert_eq!(streamed, plaintext);
+        }
+    }
+
+    #[test]
+    fn test_chunk_streams_are_independent() {
+        // 相同明文的各块密文互不相同，块的密钥流只由密钥、nonce 和块序号决定
+        let plaintext = vec![b'x'; 4 * 1024];
+        let payload = sealed(&plaintext);
+        let stride = 1024 + CHUNK_TAG_LENGTH;
+        let cipher = |index: usize| &payload[4 + index * stride..4 + index * stride + 1024];
+        assert_ne!(cipher(0), cipher(1));
+        assert_ne!(cipher(1), cipher(3));
+
+        let keys = FileKeys::derive(&Key::build(), NONCE);
+        let mut chunk = cipher(2).to_vec();
+        chacha20_at(
+            &chacha20_key(Key::build().as_bytes()),
+            &keys.chunk_nonce(2),
+            0,
+            &mut chunk,
+        );
+        assert_eq!(chunk, &plaintext[..1024]);
+
+        // 另一个 nonce 得到完全不同的密文
+        let mut other = Vec::new();
+        seal(
+            &Key::build(),
+            &[8; NONCE_LENGTH],
+            PREFIX,
+            &plaintext,
+            MIN_CHUNK_SIZE,
+            &mut other,
+        );
+        assert_ne!(&other[4..4 + 1024], cipher(0));
+    }
+
+    #[test]
+    fn test_tampering_points_to_chunk() {
+        let key = Key::build();
+        let plaintext = vec![b'x'; 8 * 1024];
+        let payload = sealed(&plaintext);
+        let stride = 1024 + CHUNK_TAG_LENGTH;
+
+        let mut damaged = payload.clone();
+        damaged[4 + 5 * stride + 10] ^= 1;
+        let chunks = Chunks::parse(PREFIX, NONCE, &damaged).unwrap();
+        let mut out = vec![0u8; plaintext.len()];
+        assert!(matches!(
+            chunks.open_all(&key, &mut out),
+            Err(Error::ChunkAuthenticationFailed(5))
+        ));
+        assert!(matches!(
+            chunks.verify_all(&key),
+            Err(Error::ChunkAuthenticationFailed(5))
+        ));
+
+        // 截掉最后一块后，新的最后一块标签不匹配
+        let truncated = &payload[..4 + 7 * stride];
+        let chunks = Chunks::parse(PREFIX, NONCE, truncated).unwrap();
+        assert!(matches!(
+            chunks.verify_all(&key),
+            Err(Error::ChunkAuthenticationFailed(6))
+        ));
+
+        // 标签绑定容器头
+        let chunks = Chunks::parse(b"other", NONCE, &payload).unwrap();
+        assert!(chunks.verify_all(&key).is_err());
+
+        assert!(matches!(
+            Chunks::parse(PREFIX, NONCE, &payload[..10]),
+            Err(Error::Truncated)
+        ));
+    }
+}
@@ -0,0 +1,1001 @@
+//! 可配置的加密器与解密器。
+//!
+//! `encrypt_content`、`decrypt_content` 等自由函数固定使用构建配置中的密钥和 `HEADER`；
+//! 需要在同一进程中使用多套配置 (例如轮换密钥期间同时解密新旧文件) 时，用
+//! [`Encryptor::builder`] / [`Decryptor::builder`] 显式指定密钥、头部、算法和预处理。
+
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use zeroize::Zeroizing;
+
+use crate::chunked::{self, ChunkReader, Chunks};
+use crate::config::{HEADER, key};
+use crate::crypto::{
+    NONCE_LENGTH, bytes_to_hex, chacha20_with, decode_with, encode_with, hex_to_bytes, key_id_of,
+    random_nonce,
+};
+use crate::error::{Error, Result};
+use crate::format::{
+    ALGORITHM_CHACHA20, ALGORITHM_XOR, ContainerHeader, FLAG_CHUNKED, FLAG_DEFLATE, FLAG_SIGNED,
+    FLAG_ZSTD, VERSION, is_container_with,
+};
+use crate::secret::{self, SecretBytes, ct_eq, ct_starts_with};
+use crate::signing::{self, SIGNATURE_LENGTH, SigningKey, VerifyingKey};
+use crate::strip::{StripOptions, strip};
+
+/// 加密密钥及其标识 (SHA-256 前 8 字节)，drop 时清零
+#[derive(Clone)]
+pub struct Key {
+    bytes: Zeroizing<Vec<u8>>,
+    id: [u8; 8],
+}
+
+impl Key {
+    pub fn new(bytes: impl Into<Vec<u8>>) -> Result<Key> {
+        let bytes = Zeroizing::new(bytes.into());
+        if bytes.is_empty() {
+            return Err(Error::Config("key must not be empty".to_string()));
+        }
+        let id = key_id_of(&bytes);
+        Ok(Key { bytes, id })
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Key> {
+        let bytes = hex_to_bytes(hex.trim())
+            .map(Zeroizing::new)
+            .ok_or_else(|| Error::Config("key is not valid hex".to_string()))?;
+        Key::new(bytes.as_slice())
+    }
+
+    /// 构建配置中的密钥
+    pub fn build() -> Key {
+        let bytes = key();
+        Key {
+            id: key_id_of(&bytes),
+            bytes,
+        }
+    }
+
+    pub fn id(&self) -> [u8; 8] {
+        self.id
+    }
+
+    pub fn id_hex(&self) -> String {
+        bytes_to_hex(&self.id)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        ct_eq(&self.bytes, &other.bytes)
+    }
+}
+
+impl Eq for Key {}
+
+/// 不输出密钥内容
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Key").field("id", &self.id_hex()).finish()
+    }
+}
+
+/// 解密时可用的一组密钥，按容器头中的密钥标识选择。
+///
+/// 第一个密钥是主密钥，用于没有密钥标识的旧格式文件。
+#[derive(Debug, Clone, Default)]
+pub struct Keyring {
+    keys: Vec<Key>,
+}
+
+impl Keyring {
+    pub fn new() -> Keyring {
+        Keyring::default()
+    }
+
+    pub fn with(mut self, key: Key) -> Keyring {
+        self.add(key);
+        self
+    }
+
+    /// 标识相同的密钥只保留先加入的一个
+    pub fn add(&mut self, key: Key) {
+        if self.find(&key.id).is_none() {
+            self.keys.push(key);
+        }
+    }
+
+    pub fn primary(&self) -> Option<&Key> {
+        self.keys.first()
+    }
+
+    pub fn find(&self, id: &[u8; 8]) -> Option<&Key> {
+        self.keys.iter().find(|key| ct_eq(&key.id, id))
+    }
+
+    pub fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+impl From<Key> for Keyring {
+    fn from(key: Key) -> Keyring {
+        Keyring::new().with(key)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    /// 与 legacy 格式相同的逐字节算法
+    #[default]
+    Xor,
+    /// ChaCha20 流密码，每个文件使用写在容器头中的随机 nonce；按块并用 SIMD 处理，
+    /// 解密大文件比 xor 快
+    ChaCha20,
+}
+
+impl Algorithm {
+    /// 写入容器头的编号
+    pub fn id(self) -> u8 {
+        match self {
+            Algorithm::Xor => ALGORITHM_XOR,
+            Algorithm::ChaCha20 => ALGORITHM_CHACHA20,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Algorithm> {
+        match id {
+            ALGORITHM_XOR => Some(Algorithm::Xor),
+            ALGORITHM_CHACHA20 => Some(Algorithm::ChaCha20),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Xor => "xor",
+            Algorithm::ChaCha20 => "chacha20",
+        }
+    }
+
+    /// 容器头是否带 nonce；不带 nonce 的算法忽略 `encode`/`decode` 的 `nonce` 参数
+    pub fn uses_nonce(self) -> bool {
+        self == Algorithm::ChaCha20
+    }
+
+    pub fn encode(self, key: &Key, nonce: &[u8; NONCE_LENGTH], data: &mut [u8]) {
+        match self {
+            Algorithm::Xor => encode_with(&key.bytes, data),
+            Algorithm::ChaCha20 => chacha20_with(&key.bytes, nonce, data),
+        }
+    }
+
+    pub fn decode(self, key: &Key, nonce: &[u8; NONCE_LENGTH], data: &mut [u8]) {
+        match self {
+            Algorithm::Xor => decode_with(&key.bytes, data),
+            Algorithm::ChaCha20 => chacha20_with(&key.bytes, nonce, data),
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Algorithm> {
+        match s {
+            "xor" => Ok(Algorithm::Xor),
+            "chacha20" => Ok(Algorithm::ChaCha20),
+            _ => Err(Error::Config(format!("unknown algorithm '{}'", s))),
+        }
+    }
+}
+
+/// 源码通常不大，压缩耗时可以忽略，使用较高的压缩级别
+const ZSTD_LEVEL: i32 = 19;
+
+/// 加密前对源码的压缩，记录在容器标志中
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Deflate,
+    Zstd,
+}
+
+impl Compression {
+    pub fn flag(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => FLAG_DEFLATE,
+            Compression::Zstd => FLAG_ZSTD,
+        }
+    }
+
+    pub fn from_flags(flags: u8) -> Result<Compression> {
+        match (flags & FLAG_DEFLATE != 0, flags & FLAG_ZSTD != 0) {
+            (false, false) => Ok(Compression::None),
+            (true, false) => Ok(Compression::Deflate),
+            (false, true) => Ok(Compression::Zstd),
+            (true, true) => Err(Error::Corrupt(
+                "both deflate and zstd flags are set".to_string(),
+            )),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Deflate => "deflate",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Zstd => Ok(zstd::stream::encode_all(data, ZSTD_LEVEL)?),
+        }
+    }
+
+    /// 解压结果在扩容时清零旧缓冲区，见 [`SecretBytes`]
+    pub fn decompress(self, data: &[u8]) -> Result<SecretBytes> {
+        // 源码的压缩率通常在 4 倍以上，预留足够容量减少扩容
+        let size_hint = data.len() * 4;
+        match self {
+            Compression::None => Ok(SecretBytes::new(data.to_vec())),
+            Compression::Deflate => secret::read_to_end(DeflateDecoder::new(data), size_hint)
+                .map_err(|e| Error::Corrupt(format!("deflate: {}", e))),
+            Compression::Zstd => zstd::stream::read::Decoder::with_buffer(data)
+                .and_then(|decoder| secret::read_to_end(decoder, size_hint))
+                .map_err(|e| Error::Corrupt(format!("zstd: {}", e))),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Compression> {
+        match s {
+            "none" => Ok(Compression::None),
+            "deflate" => Ok(Compression::Deflate),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(Error::Config(format!("unknown compression '{}'", s))),
+        }
+    }
+}
+
+type Step = Arc<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+fn validate_header(header: &[u8]) -> Result<()> {
+    if header.is_empty() {
+        return Err(Error::Config("header must not be empty".to_string()));
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct Encryptor {
+    key: Key,
+    header: Vec<u8>,
+    algorithm: Algorithm,
+    compression: Compression,
+    signing_key: Option<SigningKey>,
+    preprocess: Vec<Step>,
+    chunk_size: Option<u32>,
+}
+
+/// 构建配置中的密钥和头部，不签名、不预处理
+impl Default for Encryptor {
+    fn default() -> Encryptor {
+        Encryptor {
+            key: Key::build(),
+            header: HEADER.to_vec(),
+            algorithm: Algorithm::default(),
+            compression: Compression::default(),
+            signing_key: None,
+            preprocess: Vec::new(),
+            chunk_size: None,
+        }
+    }
+}
+
+impl Encryptor {
+    pub fn builder() -> EncryptorBuilder {
+        EncryptorBuilder {
+            encryptor: Encryptor::default(),
+        }
+    }
+
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn signing_key(&self) -> Option<&SigningKey> {
+        self.signing_key.as_ref()
+    }
+
+    /// 分块布局的块大小，`None` 为整体加密
+    pub fn chunk_size(&self) -> Option<u32> {
+        self.chunk_size
+    }
+
+    pub fn is_encrypted(&self, data: &[u8]) -> bool {
+        ct_starts_with(data, &self.header)
+    }
+
+    /// 依次执行预处理步骤后加密；已带有本加密器头部的内容返回 [`Error::AlreadyEncrypted`]
+    pub fn encrypt(&self, source: &[u8]) -> Result<Vec<u8>> {
+        if self.is_encrypted(source) {
+            return Err(Error::AlreadyEncrypted);
+        }
+
+        // 中间结果都是明文，释放前清零
+        let mut processed: Option<Zeroizing<Vec<u8>>> = None;
+        for step in &self.preprocess {
+            let input = processed.as_ref().map_or(source, |p| p.as_slice());
+            processed = Some(Zeroizing::new(step(input)));
+        }
+        let content = Zeroizing::new(
+            self.compression
+                .compress(processed.as_ref().map_or(source, |p| p.as_slice()))?,
+        );
+
+        let mut flags = self.compression.flag();
+        if self.signing_key.is_some() {
+            flags |= FLAG_SIGNED;
+        }
+        if self.chunk_size.is_some() {
+            flags |= FLAG_CHUNKED;
+        }
+        let nonce = match self.algorithm.uses_nonce() {
+            true => random_nonce()?,
+            false => [0; NONCE_LENGTH],
+        };
+        let header = ContainerHeader {
+            version: VERSION,
+            algorithm: self.algorithm.id(),
+            flags,
+            key_id: self.key.id,
+            nonce,
+        };
+        let mut result = Vec::with_capacity(
+            header.prefix_len_for(&self.header) + content.len() + SIGNATURE_LENGTH,
+        );
+        header.write_with(&self.header, &mut result);
+
+        if let Some(chunk_size) = self.chunk_size {
+            let prefix = result.clone();
+            chunked::seal(
+                &self.key,
+                &nonce,
+                &prefix,
+                &content,
+                chunk_size,
+                &mut result,
+            );
+        } else {
+            let start = result.len();
+            result.extend_from_slice(content.as_slice());
+            self.algorithm
+                .encode(&self.key, &nonce, &mut result[start..]);
+        }
+
+        if let Some(key) = &self.signing_key {
+            let signature = signing::sign(key, &result);
+            result.extend_from_slice(&signature);
+        }
+
+        Ok(result)
+    }
+
+    pub fn encrypt_file<P: AsRef<Path>, Q: AsRef<Path>>(&self, source: P, dest: Q) -> Result<()> {
+        let encrypted = self.encrypt(&fs::read(source)?)?;
+        fs::write(dest, encrypted)?;
+        Ok(())
+    }
+
+    /// 签名覆盖整个文件，因此会先读完输入再写出
+    pub fn encrypt_stream<R: Read, W: Write>(&self, mut reader: R, mut writer: W) -> Result<()> {
+        let mut source = Vec::new();
+        reader.read_to_end(&mut source)?;
+        writer.write_all(&self.encrypt(&source)?)?;
+        Ok(())
+    }
+}
+
+pub struct EncryptorBuilder {
+    encryptor: Encryptor,
+}
+
+impl EncryptorBuilder {
+    pub fn key(mut self, key: Key) -> Self {
+        self.encryptor.key = key;
+        self
+    }
+
+    /// 文件头部，解密端必须使用相同的头部
+    pub fn header(mut self, header: impl Into<Vec<u8>>) -> Self {
+        self.encryptor.header = header.into();
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.encryptor.algorithm = algorithm;
+        self
+    }
+
+    /// 在预处理之后、加密之前压缩源码
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.encryptor.compression = compression;
+        self
+    }
+
+    /// 用 Ed25519 私钥签名，扩展只持有公钥用于校验
+    pub fn signing_key(mut self, signing_key: SigningKey) -> Self {
+        self.encryptor.signing_key = Some(signing_key);
+        self
+    }
+
+    /// 追加一个加密前的源码处理步骤，按加入顺序执行
+    pub fn preprocess<F>(mut self, step: F) -> Self
+    where
+        F: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        self.encryptor.preprocess.push(Arc::new(step));
+        self
+    }
+
+    pub fn strip(self, options: StripOptions) -> Self {
+        self.preprocess(move |source| strip(source, &options))
+    }
+
+    /// 分块加密，每块带独立的认证标签，解密时可以并行或流式处理。
+    ///
+    /// 只支持 [`Algorithm::ChaCha20`]；块大小见 [`chunked::validate_chunk_size`]。
+    pub fn chunked(mut self, chunk_size: u32) -> Self {
+        self.encryptor.chunk_size = Some(chunk_size);
+        self
+    }
+
+    pub fn build(self) -> Result<Encryptor> {
+        validate_header(&self.encryptor.header)?;
+        if let Some(chunk_size) = self.encryptor.chunk_size {
+            chunked::validate_chunk_size(chunk_size)?;
+            if self.encryptor.algorithm != Algorithm::ChaCha20 {
+                return Err(Error::Config(
+                    "chunked layout requires the chacha20 algorithm".to_string(),
+                ));
+            }
+        }
+        Ok(self.encryptor)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Decryptor {
+    keyring: Keyring,
+    header: Vec<u8>,
+    verifying_key: Option<VerifyingKey>,
+    lock_memory: bool,
+}
+
+/// 构建配置中的密钥和头部，不要求签名
+impl Default for Decryptor {
+    fn default() -> Decryptor {
+        Decryptor {
+            keyring: Keyring::from(Key::build()),
+            header: HEADER.to_vec(),
+            verifying_key: None,
+            lock_memory: false,
+        }
+    }
+}
+
+impl Decryptor {
+    pub fn builder() -> DecryptorBuilder {
+        DecryptorBuilder {
+            keyring: Keyring::new(),
+            header: HEADER.to_vec(),
+            verifying_key: None,
+            lock_memory: false,
+        }
+    }
+
+    pub fn keyring(&self) -> &Keyring {
+        &self.keyring
+    }
+
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    pub fn is_encrypted(&self, data: &[u8]) -> bool {
+        ct_starts_with(data, &self.header)
+    }
+
+    /// 存放明文的缓冲区，按配置锁定内存
+    fn secret(&self, data: Vec<u8>) -> SecretBytes {
+        let mut secret = SecretBytes::new(data);
+        if self.lock_memory {
+            secret.lock();
+        }
+        secret
+    }
+
+    /// 解析容器头、查找密钥并校验签名，返回 payload 的位置
+    fn open_container<'a>(&'a self, data: &[u8]) -> Result<Opened<'a>> {
+        let header = ContainerHeader::parse_with(data, &self.header).ok_or(Error::Truncated)?;
+        if header.version != VERSION {
+            return Err(Error::UnsupportedVersion(header.version));
+        }
+        let algorithm = Algorithm::from_id(header.algorithm)
+            .ok_or(Error::UnsupportedAlgorithm(header.algorithm))?;
+        let compression = Compression::from_flags(header.flags)?;
+        let key = self.keyring.find(&header.key_id).ok_or(Error::UnknownKey)?;
+        let chunked = header.has_flag(FLAG_CHUNKED);
+        if chunked && algorithm != Algorithm::ChaCha20 {
+            return Err(Error::Corrupt(format!(
+                "chunked layout with {} algorithm",
+                algorithm.name()
+            )));
+        }
+
+        let start = header.prefix_len_for(&self.header);
+        let mut end = data.len();
+        if header.has_flag(FLAG_SIGNED) {
+            if end < start + SIGNATURE_LENGTH {
+                return Err(Error::Truncated);
+            }
+            end -= SIGNATURE_LENGTH;
+            if let Some(verifying_key) = &self.verifying_key
+                && !signing::verify(verifying_key, &data[..end], &data[end..])
+            {
+                return Err(Error::AuthenticationFailed);
+            }
+        } else if self.verifying_key.is_some() {
+            return Err(Error::NotSigned);
+        }
+
+        Ok(Opened {
+            algorithm,
+            compression,
+            key,
+            nonce: header.nonce,
+            chunked,
+            start,
+            end,
+        })
+    }
+
+    /// 解密 legacy 或容器格式的内容，返回的明文在 drop 时清零。
+    ///
+    /// 设置了公钥时只接受带有效签名的容器，未签名文件和 legacy 文件都会被拒绝。
+    /// 分块容器的各块在多个线程中并行校验和解密。
+    pub fn decrypt(&self, data: &[u8]) -> Result<SecretBytes> {
+        if !self.is_encrypted(data) {
+            return Err(Error::NotEncrypted);
+        }
+
+        if !is_container_with(data, &self.header) {
+            if self.verifying_key.is_some() {
+                return Err(Error::NotSigned);
+            }
+            let key = self.keyring.primary().ok_or(Error::UnknownKey)?;
+            let mut decrypted = self.secret(data[self.header.len()..].to_vec());
+            decode_with(&key.bytes, decrypted.as_mut_slice());
+            return Ok(decrypted);
+        }
+
+        let opened = self.open_container(data)?;
+        let payload = &data[opened.start..opened.end];
+        let decrypted = if opened.chunked {
+            let chunks = Chunks::parse(&data[..opened.start], opened.nonce, payload)?;
+            let mut decrypted = self.secret(vec![0; chunks.plaintext_len()]);
+            chunks.open_all(opened.key, decrypted.as_mut_slice())?;
+            decrypted
+        } else {
+            let mut decrypted = self.secret(payload.to_vec());
+            opened
+                .algorithm
+                .decode(opened.key, &opened.nonce, decrypted.as_mut_slice());
+            decrypted
+        };
+        match opened.compression {
+            Compression::None => Ok(decrypted),
+            compression => {
+                let mut source = compression.decompress(&decrypted)?;
+                if self.lock_memory {
+                    source.lock();
+                }
+                Ok(source)
+            }
+        }
+    }
+
+    pub fn decrypt_file<P: AsRef<Path>>(&self, path: P) -> Result<SecretBytes> {
+        self.decrypt(&fs::read(path)?)
+    }
+
+    /// 解密并写入 `writer`。
+    ///
+    /// 分块容器逐块校验、解密 (和解压) 后立即写出，内存中只保留一块明文；写出的内容在
+    /// 遇到校验失败的块时中止，调用方应丢弃已写出的部分。其他格式先整体解密再写出。
+    pub fn decrypt_to<W: Write>(&self, data: &[u8], mut writer: W) -> Result<()> {
+        if !self.is_encrypted(data) || !is_container_with(data, &self.header) {
+            writer.write_all(&self.decrypt(data)?)?;
+            return Ok(());
+        }
+        let opened = self.open_container(data)?;
+        if !opened.chunked {
+            writer.write_all(&self.decrypt(data)?)?;
+            return Ok(());
+        }
+
+        let chunks = Chunks::parse(
+            &data[..opened.start],
+            opened.nonce,
+            &data[opened.start..opened.end],
+        )?;
+        let reader = ChunkReader::new(chunks, opened.key);
+        match opened.compression {
+            Compression::None => copy_secret(reader, writer),
+            Compression::Deflate => copy_secret(DeflateDecoder::new(reader), writer),
+            Compression::Zstd => copy_secret(
+                zstd::stream::read::Decoder::new(reader)
+                    .map_err(|e| Error::Corrupt(format!("zstd: {}", e)))?,
+                writer,
+            ),
+        }
+    }
+
+    pub fn decrypt_stream<R: Read, W: Write>(&self, mut reader: R, writer: W) -> Result<()> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        self.decrypt_to(&data, writer)
+    }
+}
+
+/// [`Decryptor::open_container`] 的结果
+struct Opened<'a> {
+    algorithm: Algorithm,
+    compression: Compression,
+    key: &'a Key,
+    nonce: [u8; NONCE_LENGTH],
+    chunked: bool,
+    start: usize,
+    end: usize,
+}
+
+/// 从解密 reader 复制到 `writer`，中转缓冲区清零。
+///
+/// 块校验失败还原为 [`Error::ChunkAuthenticationFailed`]，解压失败为 [`Error::Corrupt`]。
+fn copy_secret<R: Read, W: Write>(mut reader: R, mut writer: W) -> Result<()> {
+    let mut buffer = Zeroizing::new([0u8; 8192]);
+    loop {
+        let n = match reader.read(&mut buffer[..]) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                return Err(match e.downcast::<Error>() {
+                    Ok(inner) => inner,
+                    Err(e) => Error::Corrupt(e.to_string()),
+                });
+            }
+        };
+        writer.write_all(&buffer[..n])?;
+    }
+}
+
+pub struct DecryptorBuilder {
+    keyring: Keyring,
+    header: Vec<u8>,
+    verifying_key: Option<VerifyingKey>,
+    lock_memory: bool,
+}
+
+impl DecryptorBuilder {
+    /// 加入一个密钥；未指定任何密钥时使用构建配置中的密钥
+    pub fn key(mut self, key: Key) -> Self {
+        self.keyring.add(key);
+        self
+    }
+
+    pub fn keyring(mut self, keyring: Keyring) -> Self {
+        for key in keyring.keys {
+            self.keyring.add(key);
+        }
+        self
+    }
+
+    pub fn header(mut self, header: impl Into<Vec<u8>>) -> Self {
+        self.header = header.into();
+        self
+    }
+
+    /// 要求文件带有该公钥的有效签名
+    pub fn verifying_key(mut self, verifying_key: VerifyingKey) -> Self {
+        self.verifying_key = Some(verifying_key);
+        self
+    }
+
+    /// 用 `mlock` 锁定解密结果，防止明文被换出到交换分区；锁定失败时静默继续
+    pub fn lock_memory(mut self, lock_memory: bool) -> Self {
+        self.lock_memory = lock_memory;
+        self
+    }
+
+    pub fn build(self) -> Result<Decryptor> {
+        validate_header(&self.header)?;
+        let keyring = match self.keyring.is_empty() {
+            true => Keyring::from(Key::build()),
+            false => self.keyring,
+        };
+        Ok(Decryptor {
+            keyring,
+            header: self.header,
+            verifying_key: self.verifying_key,
+            lock_memory: self.lock_memory,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::MAGIC;
+
+    const SOURCE: &[u8] = b"<?php echo 'Hello, World!';";
+
+    #[test]
+    fn test_custom_configuration() {
+        let key = Key::from_hex(&"ab".repeat(32)).unwrap();
+        let encryptor = Encryptor::builder()
+            .key(key.clone())
+            .header(b"<?php /* custom */".to_vec())
+            .build()
+            .unwrap();
+        let encrypted = encryptor.encrypt(SOURCE).unwrap();
+        assert!(matches!(
+            encryptor.encrypt(&encrypted),
+            Err(Error::AlreadyEncrypted)
+        ));
+
+        // 构建配置的解密器不认识自定义头部
+        assert!(matches!(
+            Decryptor::default().decrypt(&encrypted),
+            Err(Error::NotEncrypted)
+        ));
+
+        let decryptor = Decryptor::builder()
+            .header(encryptor.header())
+            .build()
+            .unwrap();
+        assert!(matches!(
+            decryptor.decrypt(&encrypted),
+            Err(Error::UnknownKey)
+        ));
+
+        let decryptor = Decryptor::builder()
+            .keyring(Keyring::from(Key::build()).with(key))
+            .header(encryptor.header())
+            .build()
+            .unwrap();
+        assert_eq!(decryptor.decrypt(&encrypted).unwrap().as_slice(), SOURCE);
+
+        let mut out = Vec::new();
+        decryptor.decrypt_stream(&encrypted[..], &mut out).unwrap();
+        assert_eq!(out, SOURCE);
+    }
+
+    #[test]
+    fn test_preprocess_and_signing() {
+        let signing_key = signing::signing_key_from_hex(&"11".repeat(32)).unwrap();
+        let encryptor = Encryptor::builder()
+            .preprocess(|source| [source, b" // note"].concat())
+            .strip(StripOptions::default())
+            .signing_key(signing_key.clone())
+            .build()
+            .unwrap();
+
+        let mut encrypted = Vec::new();
+        encryptor.encrypt_stream(SOURCE, &mut encrypted).unwrap();
+
+        let decryptor = Decryptor::builder()
+            .verifying_key(signing_key.verifying_key())
+            .build()
+            .unwrap();
+        assert_eq!(decryptor.decrypt(&encrypted).unwrap().as_slice(), SOURCE);
+    }
+
+    #[test]
+    fn test_compression() {
+        let source = "<?php\n".to_string() + &"echo 'repeated line';\n".repeat(200);
+        for compression in [Compression::Deflate, Compression::Zstd] {
+            let encryptor = Encryptor::builder()
+                .compression(compression)
+                .build()
+                .unwrap();
+            let encrypted = encryptor.encrypt(source.as_bytes()).unwrap();
+            assert!(encrypted.len() < source.len() / 4);
+
+            let header = ContainerHeader::parse(&encrypted).unwrap();
+            assert_eq!(Compression::from_flags(header.flags).unwrap(), compression);
+            assert_eq!(
+                Decryptor::default().decrypt(&encrypted).unwrap().as_slice(),
+                source.as_bytes()
+            );
+
+            let mut damaged = encrypted.clone();
+            let last = damaged.len() - 1;
+            damaged.truncate(last - 8);
+            assert!(matches!(
+                Decryptor::default().decrypt(&damaged),
+                Err(Error::Corrupt(_))
+            ));
+        }
+        assert_eq!("zstd".parse::<Compression>().unwrap(), Compression::Zstd);
+        assert!("lz4".parse::<Compression>().is_err());
+    }
+
+    #[test]
+    fn test_chacha20_algorithm() {
+        let source = "<?php\n".to_string() + &"echo 'chacha20';\n".repeat(100);
+        for compression in [Compression::None, Compression::Zstd] {
+            let encrypted = Encryptor::builder()
+                .algorithm(Algorithm::ChaCha20)
+                .compression(compression)
+                .build()
+                .unwrap()
+                .encrypt(source.as_bytes())
+                .unwrap();
+            let header = ContainerHeader::parse(&encrypted).unwrap();
+            assert_eq!(header.algorithm, ALGORITHM_CHACHA20);
+            assert_eq!(
+                Decryptor::default().decrypt(&encrypted).unwrap().as_slice(),
+                source.as_bytes()
+            );
+        }
+        assert_eq!(
+            "chacha20".parse::<Algorithm>().unwrap(),
+            Algorithm::ChaCha20
+        );
+        assert!("keystream".parse::<Algorithm>().is_err());
+        assert!("aes".parse::<Algorithm>().is_err());
+    }
+
+    #[test]
+    fn test_chacha20_nonce_is_per_file() {
+        let source = "<?php\necho 'same plaintext';\n";
+        let encryptor = Encryptor::builder()
+            .algorithm(Algorithm::ChaCha20)
+            .build()
+            .unwrap();
+        let first = encryptor.encrypt(source.as_bytes()).unwrap();
+        let second = encryptor.encrypt(source.as_bytes()).unwrap();
+
+        // 同一明文加密两次，nonce 和密文都不同，两份都能解密
+        let first_header = ContainerHeader::parse(&first).unwrap();
+        let second_header = ContainerHeader::parse(&second).unwrap();
+        assert_ne!(first_header.nonce, second_header.nonce);
+        let start = first_header.prefix_len();
+        assert_ne!(&first[start..], &second[start..]);
+        for encrypted in [&first, &second] {
+            assert_eq!(
+                Decryptor::default().decrypt(encrypted).unwrap().as_slice(),
+                source.as_bytes()
+            );
+        }
+
+        // nonce 被改动后解出的内容不再是原文
+        let mut damaged = first.clone();
+        damaged[start - 1] ^= 1;
+        assert_ne!(
+            Decryptor::default().decrypt(&damaged).unwrap().as_slice(),
+            source.as_bytes()
+        );
+
+        // 旧的无 nonce 密钥流 (算法 1) 不再接受
+        let mut retired = first.clone();
+        retired[HEADER.len() + MAGIC.len() + 1] = 1;
+        assert!(matches!(
+            Decryptor::default().decrypt(&retired),
+            Err(Error::UnsupportedAlgorithm(1))
+        ));
+    }
+
+    #[test]
+    fn test_chunked_layout() {
+        let source = "<?php\n".to_string() + &"$translations[] = 'text';\n".repeat(2000);
+        for compression in [Compression::None, Compression::Zstd] {
+            let encryptor = Encryptor::builder()
+                .algorithm(Algorithm::ChaCha20)
+                .compression(compression)
+                .chunked(chunked::MIN_CHUNK_SIZE)
+                .build()
+                .unwrap();
+            let encrypted = encryptor.encrypt(source.as_bytes()).unwrap();
+            let header = ContainerHeader::parse(&encrypted).unwrap();
+            assert!(header.has_flag(FLAG_CHUNKED));
+
+            let decryptor = Decryptor::default();
+            assert_eq!(
+                decryptor.decrypt(&encrypted).unwrap().as_slice(),
+                source.as_bytes()
+            );
+            let mut streamed = Vec::new();
+            decryptor.decrypt_to(&encrypted, &mut streamed).unwrap();
+            assert_eq!(streamed, source.as_bytes());
+        }
+
+        let encrypted = Encryptor::builder()
+            .algorithm(Algorithm::ChaCha20)
+            .chunked(chunked::MIN_CHUNK_SIZE)
+            .build()
+            .unwrap()
+            .encrypt(source.as_bytes())
+            .unwrap();
+        let mut damaged = encrypted.clone();
+        let chunk_start = ContainerHeader::parse(&encrypted).unwrap().prefix_len() + 4;
+        damaged[chunk_start + 3 * (1024 + chunked::CHUNK_TAG_LENGTH) + 1] ^= 1;
+        assert!(matches!(
+            Decryptor::default().decrypt(&damaged),
+            Err(Error::ChunkAuthenticationFailed(3))
+        ));
+        assert!(matches!(
+            Decryptor::default().decrypt_to(&damaged, std::io::sink()),
+            Err(Error::ChunkAuthenticationFailed(3))
+        ));
+
+        // 分块只支持 chacha20，块大小必须对齐
+        assert!(Encryptor::builder().chunked(4096).build().is_err());
+        assert!(
+            Encryptor::builder()
+                .algorithm(Algorithm::ChaCha20)
+                .chunked(4100)
+                .build()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_invalid_configuration() {
+        assert!(matches!(Key::new(Vec::new()), Err(Error::Config(_))));
+        assert!(matches!(Key::from_hex("xyz"), Err(Error::Config(_))));
+        assert!(Encryptor::builder().header(Vec::new()).build().is_err());
+        assert!(Decryptor::builder().header(Vec::new()).build().is_err());
+    }
+}
@@ -1 +1,12 @@
+use zeroize::Zeroizing;
+
 include!(concat!(env!("OUT_DIR"), "/config_generated.rs"));
+
+/// 构建配置中的密钥。
+///
+/// 密钥在二进制中拆成多段掩码存放 (见 `build.rs`)，每次调用时还原到新的缓冲区，用完即清零。
+pub fn key() -> Zeroizing<Vec<u8>> {
+    let mut key = Zeroizing::new(vec![0u8; KEY_LEN]);
+    assemble_key(&mut key);
+    key
+}
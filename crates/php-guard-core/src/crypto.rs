@@ -1,38 +1,119 @@
-use crate::config::{HEADER, KEY};
+use chacha20::ChaCha20;
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::config::{HEADER, key};
+use crate::secret::ct_starts_with;
 
 pub fn encode(data: &mut [u8]) {
-    let key_len = KEY.len();
+    encode_with(&key(), data);
+}
+
+pub fn decode(data: &mut [u8]) {
+    decode_with(&key(), data);
+}
+
+pub fn encode_with(key: &[u8], data: &mut [u8]) {
+    let key_len = key.len();
     let mut p: usize = 0;
 
     for (i, byte) in data.iter_mut().enumerate() {
         if i & 1 == 1 {
-            p = p.wrapping_add(KEY[p] as usize).wrapping_add(i);
+            p = p.wrapping_add(key[p] as usize).wrapping_add(i);
             p %= key_len;
-            let t = KEY[p];
+            let t = key[p];
             *byte = !(*byte ^ t);
         }
     }
 }
 
-pub fn decode(data: &mut [u8]) {
-    let key_len = KEY.len();
+pub fn decode_with(key: &[u8], data: &mut [u8]) {
+    let key_len = key.len();
     let mut p: usize = 0;
 
     for (i, byte) in data.iter_mut().enumerate() {
         if i & 1 == 1 {
-            p = p.wrapping_add(KEY[p] as usize).wrapping_add(i);
+            p = p.wrapping_add(key[p] as usize).wrapping_add(i);
             p %= key_len;
-            let t = KEY[p];
+            let t = key[p];
             *byte = !(*byte) ^ t;
         }
     }
 }
 
+pub const NONCE_LENGTH: usize = 12;
+
+/// 由构建密钥派生 ChaCha20 密钥；构建密钥长度不固定，先经过带域分隔的 SHA-256
+pub fn chacha20_key(key: &[u8]) -> Zeroizing<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"php-guard chacha20");
+    hasher.update(key);
+    Zeroizing::new(hasher.finalize().into())
+}
+
+/// ChaCha20 加密或解密 (两者相同)，从密钥流的第 `offset` 字节开始。
+///
+/// 每个文件使用随机 nonce，同一密钥加密的文件各自使用不同的密钥流；实现按 64 字节分块
+/// 处理并在支持的 CPU 上使用 SIMD，不像 [`decode_with`] 那样逐字节计算下标和取模。
+pub fn chacha20_at(
+    cipher_key: &[u8; 32],
+    nonce: &[u8; NONCE_LENGTH],
+    offset: u64,
+    data: &mut [u8],
+) {
+    let mut cipher = ChaCha20::new(cipher_key.into(), nonce.into());
+    cipher.seek(offset);
+    cipher.apply_keystream(data);
+}
+
+pub fn chacha20_with(key: &[u8], nonce: &[u8; NONCE_LENGTH], data: &mut [u8]) {
+    chacha20_at(&chacha20_key(key), nonce, 0, data);
+}
+
+/// 新文件的随机 nonce
+pub fn random_nonce() -> std::io::Result<[u8; NONCE_LENGTH]> {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    getrandom::fill(&mut nonce).map_err(|e| std::io::Error::other(e.to_string()))?;
+    Ok(nonce)
+}
+
 pub fn is_encrypted(data: &[u8]) -> bool {
-    if data.len() < HEADER.len() {
-        return false;
+    ct_starts_with(data, HEADER)
+}
+
+/// 密钥标识：密钥 SHA-256 的前 8 字节，用于在授权、清单中区分不同构建的密钥
+pub fn key_id_bytes() -> [u8; 8] {
+    key_id_of(&key())
+}
+
+pub fn key_id_of(key: &[u8]) -> [u8; 8] {
+    let digest = Sha256::digest(key);
+    let mut id = [0u8; 8];
+    id.copy_from_slice(&digest[..8]);
+    id
+}
+
+pub fn key_id() -> String {
+    bytes_to_hex(&key_id_bytes())
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    bytes_to_hex(&Sha256::digest(data))
+}
+
+pub fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
     }
-    &data[..HEADER.len()] == HEADER
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
 }
 
 #[cfg(test)]
@@ -52,6 +133,38 @@ mod tests {
         assert_eq!(data, original);
     }
 
+    #[test]
+    fn test_chacha20_roundtrip() {
+        let key = key();
+        let nonce = [7u8; NONCE_LENGTH];
+        for len in [0, 1, 63, 64, 65, 1000] {
+            let original: Vec<u8> = (0..len).map(|i| (i * 31) as u8).collect();
+            let mut data = original.clone();
+            chacha20_with(&key, &nonce, &mut data);
+            if len > 0 {
+                assert_ne!(data, original);
+            }
+            chacha20_with(&key, &nonce, &mut data);
+            assert_eq!(data, original);
+        }
+
+        // 密钥或 nonce 不同，密钥流都不同
+        let stream = |key: &[u8], nonce: &[u8; NONCE_LENGTH]| {
+            let mut data = vec![0u8; 256];
+            chacha20_with(key, nonce, &mut data);
+            data
+        };
+        let a = stream(b"key one", &nonce);
+        assert_ne!(a, stream(b"key two", &nonce));
+        assert_ne!(a, stream(b"key one", &[8u8; NONCE_LENGTH]));
+
+        // 任意偏移处开始的密钥流与从头计算的一致
+        let cipher_key = chacha20_key(b"key one");
+        let mut middle = vec![0u8; 100];
+        chacha20_at(&cipher_key, &nonce, 70, &mut middle);
+        assert_eq!(middle, a[70..170]);
+    }
+
     #[test]
     fn test_is_encrypted() {
         let encrypted_with_header = [HEADER, b"test"].concat();
@@ -60,4 +173,37 @@ mod tests {
         let plain = b"<?php echo 'test';";
         assert!(!is_encrypted(plain));
     }
+
+    #[test]
+    fn test_hex_roundtrip() {
+        let bytes = [0x00, 0x7f, 0xab, 0xff];
+        assert_eq!(bytes_to_hex(&bytes), "007fabff");
+        assert_eq!(hex_to_bytes("007fabff").unwrap(), bytes);
+        assert!(hex_to_bytes("abc").is_none());
+        assert!(hex_to_bytes("zz").is_none());
+        assert_eq!(key_id().len(), 16);
+    }
+
+    #[test]
+    fn test_key_reassembly() {
+        // 分段还原的结果每次一致，且与密钥标识对应
+        let key = key();
+        assert!(!key.is_empty());
+        assert_eq!(*key, *super::key());
+        assert_eq!(key_id_of(&key), key_id_bytes());
+    }
+
+    #[test]
+    fn test_key_matches_config_file() {
+        // 与构建读取的 config.env 中的明文密钥逐字节一致
+        let config = std::fs::read_to_string(env!("PHP_GUARD_CONFIG_FILE")).unwrap();
+        let configured = config
+            .lines()
+            .map(|line| line.trim().trim_start_matches("export "))
+            .find_map(|line| line.strip_prefix("PHP_GUARD_KEY="))
+            .map(|value| value.trim().trim_matches('"'))
+            .and_then(hex_to_bytes)
+            .expect("PHP_GUARD_KEY in config.env");
+        assert_eq!(*key(), configured);
+    }
 }
@@ -0,0 +1,169 @@
+//! 调试器检测策略。
+//!
+//! Xdebug、vld (opcode 导出) 和 phpdbg 可以在同一进程中导出解密后的代码。扩展在第一次
+//! 解密前检测它们，按构建时的 `PHP_GUARD_DEBUGGER_POLICY` 决定放行、警告或拒绝。
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+
+/// 能导出解密后代码的扩展 (`module_registry` 中的小写名称)
+pub const DEBUGGER_EXTENSIONS: &[&str] = &["xdebug", "vld"];
+
+/// 调试器 SAPI
+pub const DEBUGGER_SAPIS: &[&str] = &["phpdbg"];
+
+/// `dev-license` 策略下允许调试的授权功能名
+pub const DEBUG_FEATURE: &str = "debug";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebuggerPolicy {
+    /// 不检测
+    #[default]
+    Allow,
+    /// 每个请求发出一次警告，照常运行
+    Warn,
+    /// 拒绝编译受保护文件
+    Refuse,
+    /// 只有带 `debug` 功能的授权才能运行，否则拒绝
+    DevLicense,
+}
+
+impl DebuggerPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            DebuggerPolicy::Allow => "allow",
+            DebuggerPolicy::Warn => "warn",
+            DebuggerPolicy::Refuse => "refuse",
+            DebuggerPolicy::DevLicense => "dev-license",
+        }
+    }
+}
+
+impl FromStr for DebuggerPolicy {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<DebuggerPolicy> {
+        match value {
+            "allow" => Ok(DebuggerPolicy::Allow),
+            "warn" => Ok(DebuggerPolicy::Warn),
+            "refuse" => Ok(DebuggerPolicy::Refuse),
+            "dev-license" => Ok(DebuggerPolicy::DevLicense),
+            other => Err(Error::Config(format!(
+                "unknown debugger policy '{}', expected allow, warn, refuse or dev-license",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for DebuggerPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// 列出当前进程中的调试器；`loaded` 判断扩展是否已加载
+pub fn detect(loaded: impl Fn(&str) -> bool, sapi: Option<&str>) -> Vec<&'static str> {
+    let mut found: Vec<&'static str> = DEBUGGER_EXTENSIONS
+        .iter()
+        .copied()
+        .filter(|name| loaded(name))
+        .collect();
+    if let Some(sapi) = sapi
+        && let Some(name) = DEBUGGER_SAPIS.iter().find(|name| **name == sapi)
+    {
+        found.push(name);
+    }
+    found
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    Warn(String),
+    Refuse(String),
+}
+
+/// 按策略处理检测结果；`debug_licensed` 表示当前授权带有 [`DEBUG_FEATURE`]
+pub fn evaluate(policy: DebuggerPolicy, detected: &[&str], debug_licensed: bool) -> Verdict {
+    if detected.is_empty() {
+        return Verdict::Allow;
+    }
+    let names = detected.join(", ");
+    match policy {
+        DebuggerPolicy::Allow => Verdict::Allow,
+        DebuggerPolicy::Warn => Verdict::Warn(format!(
+            "debugger loaded ({}), protected code can be dumped",
+            names
+        )),
+        DebuggerPolicy::Refuse => Verdict::Refuse(format!(
+            "refusing to run protected code while a debugger is loaded ({})",
+            names
+        )),
+        DebuggerPolicy::DevLicense if debug_licensed => Verdict::Allow,
+        DebuggerPolicy::DevLicense => Verdict::Refuse(format!(
+            "debugger loaded ({}), protected code only runs under a license with the '{}' feature",
+            names, DEBUG_FEATURE
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_parse() {
+        for policy in [
+            DebuggerPolicy::Allow,
+            DebuggerPolicy::Warn,
+            DebuggerPolicy::Refuse,
+            DebuggerPolicy::DevLicense,
+        ] {
+            assert_eq!(policy.name().parse::<DebuggerPolicy>().unwrap(), policy);
+        }
+        assert!(matches!(
+            "block".parse::<DebuggerPolicy>(),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_detect() {
+        assert!(detect(|_| false, Some("fpm-fcgi")).is_empty());
+        assert_eq!(detect(|name| name == "vld", Some("cli")), vec!["vld"]);
+        assert_eq!(
+            detect(|name| name == "xdebug", Some("phpdbg")),
+            vec!["xdebug", "phpdbg"]
+        );
+        assert_eq!(detect(|_| false, None), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let found = ["xdebug"];
+        assert_eq!(evaluate(DebuggerPolicy::Refuse, &[], false), Verdict::Allow);
+        assert_eq!(
+            evaluate(DebuggerPolicy::Allow, &found, false),
+            Verdict::Allow
+        );
+        assert!(matches!(
+            evaluate(DebuggerPolicy::Warn, &found, false),
+            Verdict::Warn(m) if m.contains("xdebug")
+        ));
+        assert!(matches!(
+            evaluate(DebuggerPolicy::Refuse, &found, true),
+            Verdict::Refuse(_)
+        ));
+        assert_eq!(
+            evaluate(DebuggerPolicy::DevLicense, &found, true),
+            Verdict::Allow
+        );
+        assert!(matches!(
+            evaluate(DebuggerPolicy::DevLicense, &found, false),
+            Verdict::Refuse(m) if m.contains("'debug'")
+        ));
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+use std::io;
+
+/// 解密、加密过程中的错误。
+///
+/// [`Error::code`] 是稳定的数字编号，CLI 据此决定退出码，扩展用作 PHP 异常的 code。
+#[derive(Debug)]
+pub enum Error {
+    NotEncrypted,
+    AlreadyEncrypted,
+    Truncated,
+    UnsupportedVersion(u8),
+    UnsupportedAlgorithm(u8),
+    /// 文件由其他密钥加密 (容器中的密钥标识不匹配)
+    UnknownKey,
+    /// 要求签名但文件未签名
+    NotSigned,
+    /// 签名校验失败
+    AuthenticationFailed,
+    /// 构建配置无效，如签名公钥无法解析
+    Config(String),
+    Io(io::Error),
+    /// 解密后的载荷无法解压
+    Corrupt(String),
+    /// 分块容器中第 n 块 (从 0 开始) 的标签校验失败
+    ChunkAuthenticationFailed(usize),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn code(&self) -> i32 {
+        match self {
+            Error::NotEncrypted => 1,
+            Error::AlreadyEncrypted => 2,
+            Error::Truncated => 3,
+            Error::UnsupportedVersion(_) => 4,
+            Error::UnsupportedAlgorithm(_) => 5,
+            Error::UnknownKey => 6,
+            Error::NotSigned => 7,
+            Error::AuthenticationFailed => 8,
+            Error::Config(_) => 9,
+            Error::Io(_) => 10,
+            Error::Corrupt(_) => 11,
+            Error::ChunkAuthenticationFailed(_) => 12,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotEncrypted => f.write_str("file is not encrypted or has wrong header"),
+            Error::AlreadyEncrypted => f.write_str("file is already encrypted"),
+            Error::Truncated => f.write_str("file is truncated"),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported container version {}", version)
+            }
+            Error::UnsupportedAlgorithm(algorithm) => {
+                write!(f, "unsupported encryption algorithm {}", algorithm)
+            }
+            Error::UnknownKey => f.write_str("file was encrypted with a different key"),
+            Error::NotSigned => f.write_str("file is not signed"),
+            Error::AuthenticationFailed => f.write_str("file signature does not verify"),
+            Error::Config(message) => write!(f, "invalid build configuration: {}", message),
+            Error::Io(e) => e.fmt(f),
+            Error::Corrupt(message) => write!(f, "file is corrupt: {}", message),
+            Error::ChunkAuthenticationFailed(index) => {
+                write!(f, "chunk {} failed authentication", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// `io::Error` 不能克隆，复制时保留其 `kind` 和信息；其余变体原样复制，[`Error::code`] 不变。
+///
+/// 扩展在 MINIT 中创建解密器，失败时保存错误，之后每次编译都要交出同一个错误。
+impl Clone for Error {
+    fn clone(&self) -> Self {
+        match self {
+            Error::NotEncrypted => Error::NotEncrypted,
+            Error::AlreadyEncrypted => Error::AlreadyEncrypted,
+            Error::Truncated => Error::Truncated,
+            Error::UnsupportedVersion(version) => Error::UnsupportedVersion(*version),
+            Error::UnsupportedAlgorithm(algorithm) => Error::UnsupportedAlgorithm(*algorithm),
+            Error::UnknownKey => Error::UnknownKey,
+            Error::NotSigned => Error::NotSigned,
+            Error::AuthenticationFailed => Error::AuthenticationFailed,
+            Error::Config(message) => Error::Config(message.clone()),
+            Error::Io(e) => Error::Io(io::Error::new(e.kind(), e.to_string())),
+            Error::Corrupt(message) => Error::Corrupt(message.clone()),
+            Error::ChunkAuthenticationFailed(index) => Error::ChunkAuthenticationFailed(*index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clone_keeps_code_and_message() {
+        let errors = [
+            Error::UnknownKey,
+            Error::Config("bad public key".to_string()),
+            Error::Io(io::Error::new(io::ErrorKind::PermissionDenied, "denied")),
+            Error::ChunkAuthenticationFailed(3),
+        ];
+        for error in &errors {
+            let copy = error.clone();
+            assert_eq!(copy.code(), error.code());
+            assert_eq!(copy.to_string(), error.to_string());
+        }
+        assert!(matches!(
+            errors[2].clone(),
+            Error::Io(e) if e.kind() == io::ErrorKind::PermissionDenied
+        ));
+    }
+}
@@ -2,73 +2,58 @@ use std::fs::File;
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
 
+use crate::codec::{Decryptor, Encryptor};
 use crate::config::HEADER;
-use crate::crypto::{decode, is_encrypted};
+use crate::error::{Error, Result};
+use crate::secret::{SecretBytes, ct_eq};
+use crate::signing::{SigningKey, VerifyingKey};
 
-pub fn read_and_decrypt_file<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<u8>> {
-    let mut file = File::open(path)?;
-    let metadata = file.metadata()?;
-    let file_size = metadata.len() as usize;
-
-    if file_size < HEADER.len() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "File too small to be encrypted",
-        ));
-    }
-
-    let mut header_buf = vec![0u8; HEADER.len()];
-    file.read_exact(&mut header_buf)?;
+pub fn read_and_decrypt_file<P: AsRef<Path>>(path: P) -> Result<SecretBytes> {
+    Decryptor::default().decrypt_file(path)
+}
 
-    if header_buf.as_slice() != HEADER {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "File is not encrypted or has wrong header",
-        ));
-    }
+pub fn decrypt_content(data: &[u8]) -> Result<SecretBytes> {
+    Decryptor::default().decrypt(data)
+}
 
-    let data_len = file_size - HEADER.len();
-    let mut data = vec![0u8; data_len];
-    if data_len > 0 {
-        file.read_exact(&mut data)?;
+/// 解密 legacy 或容器格式的内容。
+///
+/// `verifying_key` 为 `Some` 时只接受带有效签名的容器，未签名文件和 legacy 文件都会被拒绝。
+pub fn decrypt_content_verified(
+    data: &[u8],
+    verifying_key: Option<&VerifyingKey>,
+) -> Result<SecretBytes> {
+    match verifying_key {
+        Some(key) => Decryptor::builder()
+            .verifying_key(*key)
+            .build()?
+            .decrypt(data),
+        None => decrypt_content(data),
     }
-
-    decode(&mut data);
-
-    Ok(data)
 }
 
-pub fn encrypt_content(content: &[u8]) -> Vec<u8> {
-    if is_encrypted(content) {
-        return content.to_vec();
+/// 已加密的内容原样返回；其他错误向上传递，不能把明文当作加密结果交给调用方
+fn seal(encryptor: &Encryptor, content: &[u8]) -> Result<Vec<u8>> {
+    match encryptor.encrypt(content) {
+        Err(Error::AlreadyEncrypted) => Ok(content.to_vec()),
+        result => result,
     }
-
-    let mut result = Vec::with_capacity(HEADER.len() + content.len());
-    result.extend_from_slice(HEADER);
-
-    let mut encrypted_content = content.to_vec();
-    crate::crypto::encode(&mut encrypted_content);
-    result.extend_from_slice(&encrypted_content);
-
-    result
 }
 
-pub fn encrypt_file<P: AsRef<Path>, Q: AsRef<Path>>(source: P, dest: Q) -> std::io::Result<()> {
-    let mut content = Vec::new();
-    File::open(source)?.read_to_end(&mut content)?;
-
-    if is_encrypted(&content) {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "File is already encrypted",
-        ));
-    }
+pub fn encrypt_content(content: &[u8]) -> Result<Vec<u8>> {
+    seal(&Encryptor::default(), content)
+}
 
-    let encrypted = encrypt_content(&content);
-    let mut dest_file = File::create(dest)?;
-    dest_file.write_all(&encrypted)?;
+/// 加密并用 Ed25519 私钥签名，扩展只持有公钥用于校验
+pub fn encrypt_content_signed(content: &[u8], signing_key: &SigningKey) -> Result<Vec<u8>> {
+    let encryptor = Encryptor::builder()
+        .signing_key(signing_key.clone())
+        .build()?;
+    seal(&encryptor, content)
+}
 
-    Ok(())
+pub fn encrypt_file<P: AsRef<Path>, Q: AsRef<Path>>(source: P, dest: Q) -> Result<()> {
+    Encryptor::default().encrypt_file(source, dest)
 }
 
 pub fn check_file_encrypted<P: AsRef<Path>>(path: P) -> std::io::Result<bool> {
@@ -83,7 +68,7 @@ pub fn check_file_encrypted<P: AsRef<Path>>(path: P) -> std::io::Result<bool> {
     let mut header_buf = vec![0u8; HEADER.len()];
     file.read_exact(&mut header_buf)?;
 
-    Ok(header_buf.as_slice() == HEADER)
+    Ok(ct_eq(&header_buf, HEADER))
 }
 
 pub fn create_temp_file_with_content(content: &[u8]) -> std::io::Result<File> {
@@ -92,3 +77,60 @@ pub fn create_temp_file_with_content(content: &[u8]) -> std::io::Result<File> {
     temp_file.seek(SeekFrom::Start(0))?;
     Ok(temp_file)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::encode;
+    use crate::format::{ContainerHeader, is_container};
+    use crate::signing;
+
+    const SOURCE: &[u8] = b"<?php echo 'Hello, World!';";
+
+    #[test]
+    fn test_container_roundtrip() {
+        let encrypted = encrypt_content(SOURCE).unwrap();
+        assert!(is_container(&encrypted));
+        assert_eq!(decrypt_content(&encrypted).unwrap().as_slice(), SOURCE);
+
+        // 只有已加密的内容原样返回
+        assert_eq!(encrypt_content(&encrypted).unwrap(), encrypted);
+    }
+
+    #[test]
+    fn test_legacy_format_still_decrypts() {
+        let mut legacy = HEADER.to_vec();
+        let mut body = SOURCE.to_vec();
+        encode(&mut body);
+        legacy.extend_from_slice(&body);
+
+        assert!(!is_container(&legacy));
+        assert_eq!(decrypt_content(&legacy).unwrap().as_slice(), SOURCE);
+    }
+
+    #[test]
+    fn test_signed_container() {
+        let key = signing::signing_key_from_hex(&"11".repeat(32)).unwrap();
+        let other = signing::signing_key_from_hex(&"22".repeat(32)).unwrap();
+        let encrypted = encrypt_content_signed(SOURCE, &key).unwrap();
+
+        let verified = decrypt_content_verified(&encrypted, Some(&key.verifying_key()));
+        assert_eq!(verified.unwrap().as_slice(), SOURCE);
+        assert!(matches!(
+            decrypt_content_verified(&encrypted, Some(&other.verifying_key())),
+            Err(Error::AuthenticationFailed)
+        ));
+
+        let mut tampered = encrypted.clone();
+        let index = ContainerHeader::parse(&encrypted).unwrap().prefix_len() + 1;
+        tampered[index] ^= 0x01;
+        assert!(decrypt_content_verified(&tampered, Some(&key.verifying_key())).is_err());
+
+        // 未签名文件在要求签名时被拒绝
+        let unsigned = encrypt_content(SOURCE).unwrap();
+        assert!(matches!(
+            decrypt_content_verified(&unsigned, Some(&key.verifying_key())),
+            Err(Error::NotSigned)
+        ));
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+
+use crate::crypto::bytes_to_hex;
+
+const FINGERPRINT_CONTEXT: &[u8] = b"php-guard-fingerprint-v1\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FingerprintSource {
+    MachineId,
+    Mac,
+    Hostname,
+    /// 客户自行提供的标识，扩展从 `php_guard.host_id` 读取
+    Custom,
+}
+
+impl FingerprintSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FingerprintSource::MachineId => "machine-id",
+            FingerprintSource::Mac => "mac",
+            FingerprintSource::Hostname => "hostname",
+            FingerprintSource::Custom => "custom",
+        }
+    }
+}
+
+impl fmt::Display for FingerprintSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FingerprintSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "machine-id" => Ok(FingerprintSource::MachineId),
+            "mac" => Ok(FingerprintSource::Mac),
+            "hostname" => Ok(FingerprintSource::Hostname),
+            "custom" => Ok(FingerprintSource::Custom),
+            other => Err(format!("unknown fingerprint source: {}", other)),
+        }
+    }
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+fn machine_id() -> Option<String> {
+    read_trimmed("/etc/machine-id").or_else(|| read_trimmed("/var/lib/dbus/machine-id"))
+}
+
+fn hostname() -> Option<String> {
+    read_trimmed("/proc/sys/kernel/hostname").or_else(|| read_trimmed("/etc/hostname"))
+}
+
+fn mac_addresses() -> Option<String> {
+    let mut macs: Vec<String> = fs::read_dir("/sys/class/net")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name() != "lo")
+        .filter_map(|entry| read_trimmed(&entry.path().join("address").to_string_lossy()))
+        .filter(|mac| mac != "00:00:00:00:00:00")
+        .collect();
+    macs.sort();
+    macs.dedup();
+    (!macs.is_empty()).then(|| macs.join(","))
+}
+
+fn read_source(source: FingerprintSource, custom: Option<&str>) -> Result<String, String> {
+    let value = match source {
+        FingerprintSource::MachineId => machine_id(),
+        FingerprintSource::Mac => mac_addresses(),
+        FingerprintSource::Hostname => hostname(),
+        FingerprintSource::Custom => custom.map(str::to_string).filter(|s| !s.is_empty()),
+    };
+    value.ok_or_else(|| format!("fingerprint source {} is not available", source))
+}
+
+/// 按给定来源计算主机指纹 (32 个十六进制字符)。任何来源缺失都视为失败，
+/// 避免在信息不全时生成一个可以在其他机器上复现的值。
+pub fn compute(sources: &[FingerprintSource], custom: Option<&str>) -> Result<String, String> {
+    if sources.is_empty() {
+        return Err("no fingerprint source selected".to_string());
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(FINGERPRINT_CONTEXT);
+    for source in sources {
+        let value = read_source(*source, custom)?;
+        hasher.update(source.as_str().as_bytes());
+        hasher.update(b"=");
+        hasher.update(value.as_bytes());
+        hasher.update(b"\n");
+    }
+
+    Ok(bytes_to_hex(&hasher.finalize()[..16]))
+}
+
+pub fn parse_sources(value: &str) -> Result<Vec<FingerprintSource>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(FingerprintSource::from_str)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_fingerprint_is_stable() {
+        let sources = [FingerprintSource::Custom];
+        let a = compute(&sources, Some("customer-42")).unwrap();
+        let b = compute(&sources, Some("customer-42")).unwrap();
+        let c = compute(&sources, Some("customer-43")).unwrap();
+
+        assert_eq!(a.len(), 32);
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert!(compute(&sources, None).is_err());
+        assert!(compute(&[], Some("x")).is_err());
+    }
+
+    #[test]
+    fn test_parse_sources() {
+        assert_eq!(
+            parse_sources("machine-id, mac").unwrap(),
+            [FingerprintSource::MachineId, FingerprintSource::Mac]
+        );
+        assert!(parse_sources("cpu").is_err());
+    }
+}
@@ -0,0 +1,203 @@
+//! 加密文件格式。
+//!
+//! 旧格式 (legacy): `HEADER | encode(source)`
+//!
+//! 容器格式 (v1):
+//!
+//! ```text
+//! HEADER | MAGIC "\0PG" | version | algorithm | flags | key_id[8] | [nonce[12]] | payload | [signature[64]]
+//! ```
+//!
+//! 只有 chacha20 算法的容器头带 nonce，每个文件随机生成。
+//!
+//! 设置 [`FLAG_CHUNKED`] 时 payload 分块存放，见 [`crate::chunked`]。
+//!
+//! legacy 的 encode 只变换奇数位字节，payload 第一个字节就是 PHP 源码首字节，
+//! 不可能是 NUL，因此两种格式可以无歧义地区分。
+
+use crate::codec::Algorithm;
+use crate::config::HEADER;
+use crate::crypto::{NONCE_LENGTH, key_id_bytes};
+use crate::secret::ct_starts_with;
+
+pub const MAGIC: &[u8] = b"\0PG";
+pub const VERSION: u8 = 1;
+
+pub const ALGORITHM_XOR: u8 = 0;
+// 1 曾是没有 nonce 的 keystream 算法，所有文件共用同一密钥流，已停用且不再接受
+pub const ALGORITHM_CHACHA20: u8 = 2;
+
+/// payload 之后附带 64 字节 Ed25519 签名，覆盖签名之前的全部字节
+pub const FLAG_SIGNED: u8 = 0x01;
+/// 源码在加密前用 deflate (RFC 1951，无 zlib 头) 压缩
+pub const FLAG_DEFLATE: u8 = 0x02;
+/// 源码在加密前用 zstd 压缩
+pub const FLAG_ZSTD: u8 = 0x04;
+/// payload 分块加密，每块带独立的认证标签
+pub const FLAG_CHUNKED: u8 = 0x08;
+
+pub fn algorithm_name(algorithm: u8) -> Option<&'static str> {
+    Algorithm::from_id(algorithm).map(Algorithm::name)
+}
+
+/// 标志位的名称，未知的位以十六进制列出
+pub fn flag_names(flags: u8) -> Vec<String> {
+    let known = [
+        (FLAG_SIGNED, "signed"),
+        (FLAG_DEFLATE, "deflate"),
+        (FLAG_ZSTD, "zstd"),
+        (FLAG_CHUNKED, "chunked"),
+    ];
+    let mut names = Vec::new();
+    let mut rest = flags;
+    for (flag, name) in known {
+        if flags & flag == flag {
+            names.push(name.to_string());
+            rest &= !flag;
+        }
+    }
+    if rest != 0 {
+        names.push(format!("0x{:02x}", rest));
+    }
+    names
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContainerHeader {
+    pub version: u8,
+    pub algorithm: u8,
+    pub flags: u8,
+    pub key_id: [u8; 8],
+    /// 不使用 nonce 的算法为全零，也不写入文件
+    pub nonce: [u8; NONCE_LENGTH],
+}
+
+/// 该算法的容器头是否带 nonce
+fn has_nonce(algorithm: u8) -> bool {
+    algorithm == ALGORITHM_CHACHA20
+}
+
+impl ContainerHeader {
+    /// MAGIC 之后的定长部分，不含 nonce
+    pub const LEN: usize = MAGIC.len() + 3 + 8;
+
+    pub fn new(flags: u8) -> ContainerHeader {
+        ContainerHeader {
+            version: VERSION,
+            algorithm: ALGORITHM_XOR,
+            flags,
+            key_id: key_id_bytes(),
+            nonce: [0; NONCE_LENGTH],
+        }
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag == flag
+    }
+
+    /// MAGIC 之后的长度，包括 nonce
+    pub fn encoded_len(&self) -> usize {
+        match has_nonce(self.algorithm) {
+            true => Self::LEN + NONCE_LENGTH,
+            false => Self::LEN,
+        }
+    }
+
+    /// 整个前缀长度 (HEADER + 容器头)
+    pub fn prefix_len(&self) -> usize {
+        self.prefix_len_for(HEADER)
+    }
+
+    pub fn prefix_len_for(&self, header: &[u8]) -> usize {
+        header.len() + self.encoded_len()
+    }
+
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        self.write_with(HEADER, out);
+    }
+
+    pub fn write_with(&self, header: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(header);
+        out.extend_from_slice(MAGIC);
+        out.push(self.version);
+        out.push(self.algorithm);
+        out.push(self.flags);
+        out.extend_from_slice(&self.key_id);
+        if has_nonce(self.algorithm) {
+            out.extend_from_slice(&self.nonce);
+        }
+    }
+
+    /// 从完整文件内容解析容器头，不是容器格式或长度不足时返回 `None`
+    pub fn parse(data: &[u8]) -> Option<ContainerHeader> {
+        Self::parse_with(data, HEADER)
+    }
+
+    pub fn parse_with(data: &[u8], header: &[u8]) -> Option<ContainerHeader> {
+        if !is_container_with(data, header) || data.len() < header.len() + Self::LEN {
+            return None;
+        }
+        let rest = &data[header.len() + MAGIC.len()..];
+        let mut parsed = ContainerHeader {
+            version: rest[0],
+            algorithm: rest[1],
+            flags: rest[2],
+            key_id: rest[3..11].try_into().unwrap(),
+            nonce: [0; NONCE_LENGTH],
+        };
+        if has_nonce(parsed.algorithm) {
+            parsed.nonce = rest.get(11..11 + NONCE_LENGTH)?.try_into().unwrap();
+        }
+        Some(parsed)
+    }
+}
+
+pub fn is_container(data: &[u8]) -> bool {
+    is_container_with(data, HEADER)
+}
+
+pub fn is_container_with(data: &[u8], header: &[u8]) -> bool {
+    ct_starts_with(data, header) && data[header.len()..].starts_with(MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let header = ContainerHeader::new(FLAG_SIGNED);
+        let mut data = Vec::new();
+        header.write_to(&mut data);
+        assert_eq!(data.len(), header.prefix_len());
+
+        let parsed = ContainerHeader::parse(&data).unwrap();
+        assert_eq!(parsed, header);
+        assert!(parsed.has_flag(FLAG_SIGNED));
+
+        assert!(ContainerHeader::parse(&data[..data.len() - 1]).is_none());
+        assert!(ContainerHeader::parse(&[HEADER, b"<?php"].concat()).is_none());
+
+        // chacha20 的 nonce 跟在密钥标识之后
+        let header = ContainerHeader {
+            algorithm: ALGORITHM_CHACHA20,
+            nonce: [9; NONCE_LENGTH],
+            ..ContainerHeader::new(0)
+        };
+        let mut data = Vec::new();
+        header.write_to(&mut data);
+        assert_eq!(
+            data.len(),
+            HEADER.len() + ContainerHeader::LEN + NONCE_LENGTH
+        );
+        assert_eq!(ContainerHeader::parse(&data), Some(header));
+        assert!(ContainerHeader::parse(&data[..data.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_flag_names() {
+        assert!(flag_names(0).is_empty());
+        assert_eq!(flag_names(FLAG_SIGNED | 0x80), ["signed", "0x80"]);
+        assert_eq!(flag_names(FLAG_SIGNED | FLAG_ZSTD), ["signed", "zstd"]);
+    }
+}
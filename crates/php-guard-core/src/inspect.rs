@@ -0,0 +1,315 @@
+//! 加密文件分类，用于排查部署问题。
+//!
+//! 与 `is_encrypted` 只看头部不同，这里会解析容器头、检查密钥标识和签名，
+//! 并试解密载荷判断密钥是否正确。
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::chunked::Chunks;
+use crate::codec::{Algorithm, Compression, Key};
+use crate::config::HEADER;
+use crate::crypto::{NONCE_LENGTH, key_id_bytes};
+use crate::format::{ContainerHeader, FLAG_CHUNKED, FLAG_SIGNED, MAGIC, VERSION, is_container};
+use crate::signing::{self, SIGNATURE_LENGTH, VerifyingKey};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContainerInfo {
+    pub header: ContainerHeader,
+    /// 密文长度，不含头部和签名
+    pub payload_len: usize,
+    pub signed: bool,
+    /// 是否用公钥校验过签名；未提供公钥时为 `None`
+    pub signature_valid: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileClass {
+    Plaintext,
+    /// 旧格式: `HEADER | XOR 密文`
+    Legacy {
+        payload_len: usize,
+    },
+    Container(ContainerInfo),
+    /// 不是本构建的头部，但内容是二进制或带有容器魔数，多半由其他构建加密
+    UnknownMagic,
+    Truncated,
+    /// 容器的密钥标识与当前密钥不一致
+    WrongKey(ContainerInfo),
+    Corrupt {
+        reason: String,
+        container: Option<ContainerInfo>,
+    },
+}
+
+impl FileClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileClass::Plaintext => "plaintext",
+            FileClass::Legacy { .. } => "legacy",
+            FileClass::Container(_) => "container",
+            FileClass::UnknownMagic => "unknown-magic",
+            FileClass::Truncated => "truncated",
+            FileClass::WrongKey(_) => "wrong-key",
+            FileClass::Corrupt { .. } => "corrupt",
+        }
+    }
+
+    /// 当前构建的扩展能否解密运行
+    pub fn is_decryptable(&self) -> bool {
+        match self {
+            FileClass::Legacy { .. } => true,
+            FileClass::Container(info) => info.signature_valid != Some(false),
+            _ => false,
+        }
+    }
+
+    pub fn container(&self) -> Option<&ContainerInfo> {
+        match self {
+            FileClass::Container(info) | FileClass::WrongKey(info) => Some(info),
+            FileClass::Corrupt { container, .. } => container.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// 解密后的内容应当是文本；密钥不对时奇数位字节会变成随机值
+fn looks_like_text(data: &[u8]) -> bool {
+    let sample = &data[..data.len().min(512)];
+    let control = sample
+        .iter()
+        .filter(|&&c| (c < 0x20 && !matches!(c, b'\t' | b'\n' | b'\r' | 0x0c)) || c == 0x7f)
+        .count();
+    control * 20 <= sample.len()
+}
+
+/// 各算法都从载荷开头解密，只取前 512 字节试解密即可
+fn decodes_to_text(algorithm: Algorithm, nonce: &[u8; NONCE_LENGTH], payload: &[u8]) -> bool {
+    let mut sample = payload[..payload.len().min(512)].to_vec();
+    algorithm.decode(&Key::build(), nonce, &mut sample);
+    looks_like_text(&sample)
+}
+
+pub fn classify(data: &[u8]) -> FileClass {
+    classify_verified(data, None)
+}
+
+/// 提供公钥时同时校验签名，结果记录在 [`ContainerInfo::signature_valid`]
+pub fn classify_verified(data: &[u8], verifying_key: Option<&VerifyingKey>) -> FileClass {
+    if !data.starts_with(HEADER) {
+        // 只有头部的一部分
+        if !data.is_empty() && HEADER.starts_with(data) {
+            return FileClass::Truncated;
+        }
+        let foreign_container = data.get(HEADER.len()..HEADER.len() + MAGIC.len()) == Some(MAGIC);
+        if foreign_container || !looks_like_text(&data[..data.len().min(HEADER.len())]) {
+            return FileClass::UnknownMagic;
+        }
+        return FileClass::Plaintext;
+    }
+
+    let body = &data[HEADER.len()..];
+    if body.is_empty() || (MAGIC.starts_with(body) && body.len() < MAGIC.len()) {
+        return FileClass::Truncated;
+    }
+
+    if !is_container(data) {
+        if !decodes_to_text(Algorithm::Xor, &[0; NONCE_LENGTH], body) {
+            return FileClass::Corrupt {
+                reason: "payload does not decrypt to text; wrong key or damaged file".to_string(),
+                container: None,
+            };
+        }
+        return FileClass::Legacy {
+            payload_len: body.len(),
+        };
+    }
+
+    let Some(header) = ContainerHeader::parse(data) else {
+        return FileClass::Truncated;
+    };
+    let start = header.prefix_len();
+    let signed = header.has_flag(FLAG_SIGNED);
+    if signed && data.len() < start + SIGNATURE_LENGTH {
+        return FileClass::Truncated;
+    }
+    let end = match signed {
+        true => data.len() - SIGNATURE_LENGTH,
+        false => data.len(),
+    };
+
+    let mut info = ContainerInfo {
+        header,
+        payload_len: end - start,
+        signed,
+        signature_valid: None,
+    };
+    let corrupt = |reason: String, info: ContainerInfo| FileClass::Corrupt {
+        reason,
+        container: Some(info),
+    };
+
+    if header.version != VERSION {
+        return corrupt(
+            format!("unsupported container version {}", header.version),
+            info,
+        );
+    }
+    let Some(algorithm) = Algorithm::from_id(header.algorithm) else {
+        return corrupt(format!("unsupported algorithm {}", header.algorithm), info);
+    };
+    if header.key_id != key_id_bytes() {
+        return FileClass::WrongKey(info);
+    }
+    if signed && let Some(key) = verifying_key {
+        info.signature_valid = Some(signing::verify(key, &data[..end], &data[end..]));
+    }
+    let compression = match Compression::from_flags(header.flags) {
+        Ok(compression) => compression,
+        Err(e) => return corrupt(e.to_string(), info),
+    };
+    // 分块容器有认证标签，校验通过即可确认密钥和内容无误
+    if header.has_flag(FLAG_CHUNKED) {
+        return match Chunks::parse(&data[..start], header.nonce, &data[start..end])
+            .and_then(|chunks| chunks.verify_all(&Key::build()))
+        {
+            Ok(()) => FileClass::Container(info),
+            Err(e) => corrupt(e.to_string(), info),
+        };
+    }
+    if compression != Compression::None {
+        let mut payload = data[start..end].to_vec();
+        algorithm.decode(&Key::build(), &header.nonce, &mut payload);
+        return match compression.decompress(&payload) {
+            Ok(source) if looks_like_text(&source) => FileClass::Container(info),
+            Ok(_) => corrupt("payload does not decompress to text".to_string(), info),
+            Err(e) => corrupt(e.to_string(), info),
+        };
+    }
+    if !decodes_to_text(algorithm, &header.nonce, &data[start..end]) {
+        return corrupt("payload does not decrypt to text".to_string(), info);
+    }
+
+    FileClass::Container(info)
+}
+
+pub fn classify_file<P: AsRef<Path>>(
+    path: P,
+    verifying_key: Option<&VerifyingKey>,
+) -> io::Result<FileClass> {
+    Ok(classify_verified(&fs::read(path)?, verifying_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Encryptor;
+    use crate::crypto::encode;
+    use crate::{encrypt_content, encrypt_content_signed};
+
+    const SOURCE: &[u8] = b"<?php echo 'Hello, World!';";
+
+    #[test]
+    fn test_classify_formats() {
+        assert_eq!(classify(SOURCE), FileClass::Plaintext);
+
+        let mut body = SOURCE.to_vec();
+        encode(&mut body);
+        let legacy = [HEADER, &body].concat();
+        assert_eq!(
+            classify(&legacy),
+            FileClass::Legacy {
+                payload_len: SOURCE.len()
+            }
+        );
+
+        let container = encrypt_content(SOURCE).unwrap();
+        let FileClass::Container(info) = classify(&container) else {
+            panic!("expected container");
+        };
+        assert_eq!(info.payload_len, SOURCE.len());
+        assert!(!info.signed);
+        assert!(classify(&container).is_decryptable());
+    }
+
+    #[test]
+    fn test_classify_signed() {
+        let key = signing::signing_key_from_hex(&"11".repeat(32)).unwrap();
+        let other = signing::signing_key_from_hex(&"22".repeat(32)).unwrap();
+        let signed = encrypt_content_signed(SOURCE, &key).unwrap();
+
+        let class = classify_verified(&signed, Some(&key.verifying_key()));
+        assert_eq!(class.container().unwrap().signature_valid, Some(true));
+        assert!(class.is_decryptable());
+
+        let class = classify_verified(&signed, Some(&other.verifying_key()));
+        assert_eq!(class.container().unwrap().signature_valid, Some(false));
+        assert!(!class.is_decryptable());
+    }
+
+    #[test]
+    fn test_classify_problems() {
+        let container = encrypt_content(SOURCE).unwrap();
+
+        assert_eq!(classify(&HEADER[..4]), FileClass::Truncated);
+        assert_eq!(classify(HEADER), FileClass::Truncated);
+        assert_eq!(
+            classify(&container[..HEADER.len() + 5]),
+            FileClass::Truncated
+        );
+
+        let mut foreign = container.clone();
+        foreign[0] ^= 0xff;
+        assert_eq!(classify(&foreign), FileClass::UnknownMagic);
+
+        let mut wrong_key = container.clone();
+        wrong_key[HEADER.len() + MAGIC.len() + 3] ^= 0xff;
+        assert_eq!(classify(&wrong_key).name(), "wrong-key");
+
+        let mut bad_version = container.clone();
+        bad_version[HEADER.len() + MAGIC.len()] = 9;
+        assert_eq!(classify(&bad_version).name(), "corrupt");
+
+        let garbage = [HEADER, &[0x01u8; 64][..]].concat();
+        assert_eq!(classify(&garbage).name(), "corrupt");
+
+        let compressed = Encryptor::builder()
+            .compression(Compression::Zstd)
+            .build()
+            .unwrap()
+            .encrypt(SOURCE)
+            .unwrap();
+        assert_eq!(classify(&compressed).name(), "container");
+
+        let chacha20 = Encryptor::builder()
+            .algorithm(Algorithm::ChaCha20)
+            .build()
+            .unwrap()
+            .encrypt(SOURCE)
+            .unwrap();
+        assert_eq!(classify(&chacha20).name(), "container");
+        let chunked = Encryptor::builder()
+            .algorithm(Algorithm::ChaCha20)
+            .chunked(crate::chunked::MIN_CHUNK_SIZE)
+            .build()
+            .unwrap()
+            .encrypt(&SOURCE.repeat(100))
+            .unwrap();
+        assert_eq!(classify(&chunked).name(), "container");
+        let mut damaged = chunked.clone();
+        damaged[ContainerHeader::parse(&chunked).unwrap().prefix_len() + 4 + 1040 + 8] ^= 1;
+        match classify(&damaged) {
+            FileClass::Corrupt { reason, .. } => assert!(reason.contains("chunk 1")),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut bad_algorithm = chacha20.clone();
+        bad_algorithm[HEADER.len() + MAGIC.len() + 1] = 7;
+        assert_eq!(classify(&bad_algorithm).name(), "corrupt");
+        assert_eq!(
+            classify(&compressed[..compressed.len() - 4]).name(),
+            "corrupt"
+        );
+    }
+}
@@ -0,0 +1,466 @@
+//! PHP 词法分析，供加密前的预处理使用。
+//!
+//! 只区分预处理需要的记号，运算符按单字节切分。输入按字节处理，不要求 UTF-8；
+//! 未闭合的字符串、注释会吞掉剩余内容并标记 `terminated = false`，由调用方决定如何处理。
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    InlineHtml,
+    /// `<?php` 及其后的一个空白字符，或 `<?=`
+    OpenTag,
+    /// `?>` 及紧随的一个换行
+    CloseTag,
+    Whitespace,
+    /// `//` 或 `#` 单行注释，不含结尾换行
+    LineComment,
+    BlockComment,
+    DocComment,
+    Variable,
+    Ident,
+    Number,
+    /// 单引号字符串
+    String,
+    /// 双引号或反引号字符串，可能包含变量插值
+    InterpolatedString,
+    Heredoc,
+    Nowdoc,
+    /// `#[` 属性起始
+    AttributeStart,
+    Punct,
+    /// `__halt_compiler();` 之后的原始数据
+    HaltData,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a [u8],
+    pub offset: usize,
+    /// 从 1 开始的行号
+    pub line: usize,
+    pub terminated: bool,
+}
+
+impl Token<'_> {
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self.kind,
+            TokenKind::Whitespace
+                | TokenKind::LineComment
+                | TokenKind::BlockComment
+                | TokenKind::DocComment
+        )
+    }
+
+    pub fn is_punct(&self, c: u8) -> bool {
+        self.kind == TokenKind::Punct && self.text == [c]
+    }
+
+    pub fn is_ident(&self, name: &str) -> bool {
+        self.kind == TokenKind::Ident && self.text.eq_ignore_ascii_case(name.as_bytes())
+    }
+}
+
+pub fn is_ident_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_' || c >= 0x80
+}
+
+pub fn is_ident_char(c: u8) -> bool {
+    is_ident_start(c) || c.is_ascii_digit()
+}
+
+pub fn tokenize(src: &[u8]) -> Vec<Token<'_>> {
+    Lexer {
+        src,
+        pos: 0,
+        line: 1,
+        tokens: Vec::new(),
+    }
+    .run()
+}
+
+struct Lexer<'a> {
+    src: &'a [u8],
+    pos: usize,
+    line: usize,
+    tokens: Vec<Token<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self, offset: usize) -> u8 {
+        self.src.get(self.pos + offset).copied().unwrap_or(0)
+    }
+
+    fn starts_with(&self, s: &[u8]) -> bool {
+        self.src[self.pos..].starts_with(s)
+    }
+
+    fn push(&mut self, kind: TokenKind, end: usize, terminated: bool) {
+        let text = &self.src[self.pos..end];
+        self.tokens.push(Token {
+            kind,
+            text,
+            offset: self.pos,
+            line: self.line,
+            terminated,
+        });
+        self.line += text.iter().filter(|&&c| c == b'\n').count();
+        self.pos = end;
+    }
+
+    fn run(mut self) -> Vec<Token<'a>> {
+        let mut in_php = false;
+        // 见到 __halt_compiler 后，下一个 `;` 或 `?>` 之后都是原始数据
+        let mut halting = false;
+
+        while self.pos < self.src.len() {
+            if !in_php {
+                self.lex_inline_html();
+                in_php = true;
+                continue;
+            }
+
+            let c = self.peek(0);
+            match c {
+                b' ' | b'\t' | b'\r' | b'\n' | 0x0b | 0x0c => {
+                    let end = self.scan_while(self.pos, |c| c.is_ascii_whitespace() || c == 0x0b);
+                    self.push(TokenKind::Whitespace, end, true);
+                }
+                b'#' if self.peek(1) == b'[' => {
+                    self.push(TokenKind::AttributeStart, self.pos + 2, true)
+                }
+                b'#' => self.lex_line_comment(),
+                b'/' if self.peek(1) == b'/' => self.lex_line_comment(),
+                b'/' if self.peek(1) == b'*' => self.lex_block_comment(),
+                b'?' if self.peek(1) == b'>' => {
+                    let mut end = self.pos + 2;
+                    if self.src[end..].starts_with(b"\r\n") {
+                        end += 2;
+                    } else if self.src.get(end) == Some(&b'\n') {
+                        end += 1;
+                    }
+                    self.push(TokenKind::CloseTag, end, true);
+                    in_php = false;
+                    if halting {
+                        self.push_halt_data();
+                    }
+                }
+                b'$' if is_ident_start(self.peek(1)) => {
+                    let end = self.scan_while(self.pos + 1, is_ident_char);
+                    self.push(TokenKind::Variable, end, true);
+                }
+                c if is_ident_start(c) => {
+                    let end = self.scan_while(self.pos, is_ident_char);
+                    self.push(TokenKind::Ident, end, true);
+                    if self.tokens.last().unwrap().is_ident("__halt_compiler") {
+                        halting = true;
+                    }
+                }
+                c if c.is_ascii_digit() || (c == b'.' && self.peek(1).is_ascii_digit()) => {
+                    let end = self.scan_number();
+                    self.push(TokenKind::Number, end, true);
+                }
+                b'\'' => {
+                    let (end, terminated) = self.scan_single_quoted(self.pos);
+                    self.push(TokenKind::String, end, terminated);
+                }
+                b'"' | b'`' => {
+                    let (end, terminated) = self.scan_interpolated(self.pos);
+                    self.push(TokenKind::InterpolatedString, end, terminated);
+                }
+                b'<' if self.starts_with(b"<<<") && self.lex_heredoc() => {}
+                _ => {
+                    self.push(TokenKind::Punct, self.pos + 1, true);
+                    if halting && c == b';' {
+                        self.push_halt_data();
+                    }
+                }
+            }
+        }
+
+        self.tokens
+    }
+
+    fn push_halt_data(&mut self) {
+        if self.pos < self.src.len() {
+            self.push(TokenKind::HaltData, self.src.len(), true);
+        }
+    }
+
+    fn scan_while(&self, mut pos: usize, pred: impl Fn(u8) -> bool) -> usize {
+        while pos < self.src.len() && pred(self.src[pos]) {
+            pos += 1;
+        }
+        pos
+    }
+
+    fn lex_inline_html(&mut self) {
+        let mut pos = self.pos;
+        while pos < self.src.len() {
+            if self.src[pos..].starts_with(b"<?=") {
+                self.push_html(pos);
+                self.push(TokenKind::OpenTag, pos + 3, true);
+                return;
+            }
+            let rest = &self.src[pos..];
+            if rest.len() >= 5 && rest[..5].eq_ignore_ascii_case(b"<?php") {
+                let after = pos + 5;
+                match self.src.get(after) {
+                    None => {
+                        self.push_html(pos);
+                        self.push(TokenKind::OpenTag, after, true);
+                        return;
+                    }
+                    Some(b'\r') if self.src.get(after + 1) == Some(&b'\n') => {
+                        self.push_html(pos);
+                        self.push(TokenKind::OpenTag, after + 2, true);
+                        return;
+                    }
+                    Some(c) if c.is_ascii_whitespace() => {
+                        self.push_html(pos);
+                        self.push(TokenKind::OpenTag, after + 1, true);
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+            pos += 1;
+        }
+        self.push_html(pos);
+    }
+
+    fn push_html(&mut self, end: usize) {
+        if end > self.pos {
+            self.push(TokenKind::InlineHtml, end, true);
+        }
+    }
+
+    /// 单行注释在换行或 `?>` 前结束
+    fn lex_line_comment(&mut self) {
+        let mut end = self.pos;
+        while end < self.src.len() {
+            if self.src[end] == b'\n' || self.src[end..].starts_with(b"?>") {
+                break;
+            }
+            end += 1;
+        }
+        self.push(TokenKind::LineComment, end, true);
+    }
+
+    fn lex_block_comment(&mut self) {
+        let is_doc = self.starts_with(b"/**") && self.peek(3).is_ascii_whitespace();
+        let kind = if is_doc {
+            TokenKind::DocComment
+        } else {
+            TokenKind::BlockComment
+        };
+        match find(&self.src[self.pos + 2..], b"*/") {
+            Some(i) => self.push(kind, self.pos + 2 + i + 2, true),
+            None => self.push(kind, self.src.len(), false),
+        }
+    }
+
+    fn scan_number(&self) -> usize {
+        let mut pos = self.pos;
+        let hex_or_bin = self.peek(0) == b'0' && matches!(self.peek(1) | 0x20, b'x' | b'b' | b'o');
+        let mut seen_dot = false;
+        while pos < self.src.len() {
+            let c = self.src[pos];
+            if c.is_ascii_alphanumeric() || c == b'_' {
+                if !hex_or_bin
+                    && (c | 0x20) == b'e'
+                    && matches!(self.src.get(pos + 1), Some(b'+' | b'-'))
+                {
+                    pos += 1;
+                }
+                pos += 1;
+            } else if c == b'.' && !seen_dot && !hex_or_bin {
+                seen_dot = true;
+                pos += 1;
+            } else {
+                break;
+            }
+        }
+        pos
+    }
+
+    fn scan_single_quoted(&self, start: usize) -> (usize, bool) {
+        let mut pos = start + 1;
+        while pos < self.src.len() {
+            match self.src[pos] {
+                b'\\' => pos += 2,
+                b'\'' => return (pos + 1, true),
+                _ => pos += 1,
+            }
+        }
+        (self.src.len(), false)
+    }
+
+    /// `start` 指向开头的 `"` 或反引号
+    fn scan_interpolated(&self, start: usize) -> (usize, bool) {
+        let quote = self.src[start];
+        let mut pos = start + 1;
+        while pos < self.src.len() {
+            match self.src[pos] {
+                b'\\' => pos += 2,
+                b'{' if self.src.get(pos + 1) == Some(&b'$') => match self.scan_braced(pos) {
+                    Some(end) => pos = end,
+                    None => return (self.src.len(), false),
+                },
+                c if c == quote => return (pos + 1, true),
+                _ => pos += 1,
+            }
+        }
+        (self.src.len(), false)
+    }
+
+    /// 跳过 `{$...}` 插值，其中可以包含带引号的数组键
+    fn scan_braced(&self, start: usize) -> Option<usize> {
+        let mut depth = 0usize;
+        let mut pos = start;
+        while pos < self.src.len() {
+            match self.src[pos] {
+                b'{' => {
+                    depth += 1;
+                    pos += 1;
+                }
+                b'}' => {
+                    depth -= 1;
+                    pos += 1;
+                    if depth == 0 {
+                        return Some(pos);
+                    }
+                }
+                b'\'' => pos = self.scan_single_quoted(pos).0,
+                b'"' | b'`' => pos = self.scan_interpolated(pos).0,
+                _ => pos += 1,
+            }
+        }
+        None
+    }
+
+    /// `<<<LABEL`、`<<<"LABEL"`、`<<<'LABEL'`，结束标记可以缩进 (PHP 7.3+)。
+    /// 不是合法的 heredoc 开头时返回 false，由调用方按普通运算符处理。
+    fn lex_heredoc(&mut self) -> bool {
+        let mut pos = self.scan_while(self.pos + 3, |c| c == b' ' || c == b'\t');
+        let quote = match self.src.get(pos) {
+            Some(&q @ (b'\'' | b'"')) => {
+                pos += 1;
+                Some(q)
+            }
+            _ => None,
+        };
+        if !self.src.get(pos).copied().is_some_and(is_ident_start) {
+            return false;
+        }
+        let label_end = self.scan_while(pos, is_ident_char);
+        let label = &self.src[pos..label_end];
+        pos = label_end;
+        if let Some(q) = quote {
+            if self.src.get(pos) != Some(&q) {
+                return false;
+            }
+            pos += 1;
+        }
+        if self.src[pos..].starts_with(b"\r\n") {
+            pos += 2;
+        } else if self.src.get(pos) == Some(&b'\n') {
+            pos += 1;
+        } else {
+            return false;
+        }
+
+        let kind = if quote == Some(b'\'') {
+            TokenKind::Nowdoc
+        } else {
+            TokenKind::Heredoc
+        };
+
+        let mut line_start = pos;
+        while line_start < self.src.len() {
+            let candidate = self.scan_while(line_start, |c| c == b' ' || c == b'\t');
+            let rest = &self.src[candidate..];
+            if rest.starts_with(label) && !rest.get(label.len()).copied().is_some_and(is_ident_char)
+            {
+                self.push(kind, candidate + label.len(), true);
+                return true;
+            }
+            match find(&self.src[line_start..], b"\n") {
+                Some(i) => line_start += i + 1,
+                None => break,
+            }
+        }
+        self.push(kind, self.src.len(), false);
+        true
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(src.as_bytes())
+            .into_iter()
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .map(|t| (t.kind, std::str::from_utf8(t.text).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize_basic() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("<html><?php $a = 'x' . \"{$b['k']}\"; # c\n#[Attr]\n?>\nend"),
+            [
+                (InlineHtml, "<html>"),
+                (OpenTag, "<?php "),
+                (Variable, "$a"),
+                (Punct, "="),
+                (String, "'x'"),
+                (Punct, "."),
+                (InterpolatedString, "\"{$b['k']}\""),
+                (Punct, ";"),
+                (LineComment, "# c"),
+                (AttributeStart, "#["),
+                (Ident, "Attr"),
+                (Punct, "]"),
+                (CloseTag, "?>\n"),
+                (InlineHtml, "end"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_heredoc_and_halt_compiler() {
+        let src = "<?php\n$s = <<<EOT\n  a // b\n  EOT;\n__halt_compiler(); /* raw */ data";
+        let tokens = tokenize(src.as_bytes());
+        let heredoc = tokens
+            .iter()
+            .find(|t| t.kind == TokenKind::Heredoc)
+            .unwrap();
+        assert_eq!(heredoc.text, b"<<<EOT\n  a // b\n  EOT");
+        assert_eq!(heredoc.line, 2);
+
+        let last = tokens.last().unwrap();
+        assert_eq!(last.kind, TokenKind::HaltData);
+        assert_eq!(last.text, b" /* raw */ data");
+    }
+
+    #[test]
+    fn test_line_comment_ends_at_close_tag() {
+        let tokens = kinds("<?php // note ?>html");
+        assert_eq!(tokens[1], (TokenKind::LineComment, "// note "));
+        assert_eq!(tokens[2], (TokenKind::CloseTag, "?>"));
+    }
+
+    #[test]
+    fn test_unterminated() {
+        let tokens = tokenize(b"<?php /* open");
+        assert!(!tokens.last().unwrap().terminated);
+    }
+}
@@ -1,10 +1,32 @@
+pub mod chunked;
+pub mod codec;
 pub mod config;
 pub mod crypto;
+pub mod debugger;
+pub mod error;
 pub mod file_handler;
+pub mod fingerprint;
+pub mod format;
+pub mod inspect;
+pub mod lexer;
+pub mod license;
+pub mod lint;
+pub mod secret;
+pub mod signing;
+pub mod strip;
 
-pub use config::{HEADER, KEY};
-pub use crypto::{decode, encode, is_encrypted};
+pub use codec::{Algorithm, Compression, Decryptor, Encryptor, Key, Keyring};
+pub use config::{HEADER, key};
+pub use crypto::{decode, encode, is_encrypted, key_id};
+pub use debugger::DebuggerPolicy;
+pub use error::{Error, Result};
 pub use file_handler::{
-    check_file_encrypted, create_temp_file_with_content, encrypt_content, encrypt_file,
-    read_and_decrypt_file,
+    check_file_encrypted, create_temp_file_with_content, decrypt_content, decrypt_content_verified,
+    encrypt_content, encrypt_content_signed, encrypt_file, read_and_decrypt_file,
 };
+pub use fingerprint::FingerprintSource;
+pub use inspect::{ContainerInfo, FileClass, classify, classify_file, classify_verified};
+pub use license::{Date, License, LicenseError, LicenseStatus};
+pub use lint::{SyntaxError, check_delimiters};
+pub use secret::SecretBytes;
+pub use strip::{StripOptions, strip};
@@ -0,0 +1,603 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::crypto::{bytes_to_hex, hex_to_bytes, key_id};
+use crate::fingerprint::{self, FingerprintSource};
+use crate::signing::{self, SigningKey, VerifyingKey};
+
+const LICENSE_BANNER: &str = "# PHP-Guard License";
+const SIGNATURE_CONTEXT: &[u8] = b"php-guard-license-v2\n";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LicenseError {
+    Malformed(String),
+    MissingField(&'static str),
+    InvalidDate(String),
+    BadSignature,
+    /// 构建没有配置 `PHP_GUARD_SIGN_PUBKEY`，无法校验授权签名
+    NoPublicKey,
+    KeyMismatch {
+        expected: String,
+        found: String,
+    },
+    Expired {
+        expires: Date,
+    },
+    HostNotAllowed {
+        fingerprint: String,
+    },
+    Fingerprint(String),
+    SapiNotAllowed {
+        sapi: String,
+    },
+    DomainNotAllowed {
+        host: Option<String>,
+    },
+}
+
+impl fmt::Display for LicenseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LicenseError::Malformed(line) => write!(f, "malformed license line: {}", line),
+            LicenseError::MissingField(field) => write!(f, "license field missing: {}", field),
+            LicenseError::InvalidDate(value) => write!(f, "invalid license date: {}", value),
+            LicenseError::BadSignature => write!(f, "license signature does not verify"),
+            LicenseError::NoPublicKey => write!(
+                f,
+                "license verification requires PHP_GUARD_SIGN_PUBKEY in the build configuration"
+            ),
+            LicenseError::KeyMismatch { expected, found } => write!(
+                f,
+                "license was issued for key {} but this build uses key {}",
+                found, expected
+            ),
+            LicenseError::Expired { expires } => write!(f, "license expired on {}", expires),
+            LicenseError::HostNotAllowed { fingerprint } => {
+                write!(f, "host fingerprint {} is not licensed", fingerprint)
+            }
+            LicenseError::Fingerprint(message) => write!(f, "{}", message),
+            LicenseError::SapiNotAllowed { sapi } => write!(f, "SAPI {} is not licensed", sapi),
+            LicenseError::DomainNotAllowed { host: Some(host) } => {
+                write!(f, "domain {} is not licensed", host)
+            }
+            LicenseError::DomainNotAllowed { host: None } => {
+                write!(
+                    f,
+                    "license is restricted to domains but the request has no host"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for LicenseError {}
+
+/// 公历日期 (UTC)，内部以 1970-01-01 起的天数计算
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    days: i64,
+}
+
+impl Date {
+    pub fn from_ymd(year: i64, month: u32, day: u32) -> Option<Date> {
+        if !(1..=12).contains(&month) || day == 0 || day > 31 {
+            return None;
+        }
+        let date = Date {
+            days: days_from_civil(year, month, day),
+        };
+        // 2 月 30 日之类的日期换算回来会落到下个月
+        (date.ymd() == (year, month, day)).then_some(date)
+    }
+
+    pub fn from_days(days: i64) -> Date {
+        Date { days }
+    }
+
+    pub fn today() -> Date {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        Date::from_days(secs.div_euclid(86_400))
+    }
+
+    pub fn days(&self) -> i64 {
+        self.days
+    }
+
+    pub fn ymd(&self) -> (i64, u32, u32) {
+        civil_from_days(self.days)
+    }
+
+    pub fn parse(value: &str) -> Result<Date, LicenseError> {
+        let invalid = || LicenseError::InvalidDate(value.to_string());
+        let mut parts = value.trim().splitn(3, '-');
+        let year = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(invalid)?;
+        let month = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(invalid)?;
+        let day = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(invalid)?;
+        Date::from_ymd(year, month, day).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (y, m, d) = self.ymd();
+        write!(f, "{:04}-{:02}-{:02}", y, m, d)
+    }
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LicenseStatus {
+    Valid,
+    /// 已过期但仍在宽限期内
+    Grace {
+        days_left: i64,
+    },
+    Expired,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct License {
+    pub customer: String,
+    /// `None` 表示永久授权
+    pub expires: Option<Date>,
+    pub grace_days: u32,
+    pub features: Vec<String>,
+    pub key_id: String,
+    /// 计算主机指纹使用的来源，`hosts` 非空时生效
+    pub bind: Vec<FingerprintSource>,
+    /// 允许运行的主机指纹，空表示不绑定主机
+    pub hosts: Vec<String>,
+    /// 允许的 `HTTP_HOST`/`SERVER_NAME`，支持 `*.example.com`，空表示不限制
+    pub domains: Vec<String>,
+    /// 允许的 SAPI 名称 (如 `fpm-fcgi`、`cli`)，空表示不限制
+    pub sapis: Vec<String>,
+}
+
+impl License {
+    pub fn new(customer: &str) -> License {
+        License {
+            customer: customer.to_string(),
+            expires: None,
+            grace_days: 0,
+            features: Vec::new(),
+            key_id: key_id(),
+            bind: Vec::new(),
+            hosts: Vec::new(),
+            domains: Vec::new(),
+            sapis: Vec::new(),
+        }
+    }
+
+    pub fn check_sapi(&self, sapi: &str) -> Result<(), LicenseError> {
+        if self.sapis.is_empty() || self.sapis.iter().any(|s| s == sapi) {
+            Ok(())
+        } else {
+            Err(LicenseError::SapiNotAllowed {
+                sapi: sapi.to_string(),
+            })
+        }
+    }
+
+    /// 检查请求域名；限制了域名时没有域名的请求 (如 CLI) 也会被拒绝
+    pub fn check_domain(&self, host: Option<&str>) -> Result<(), LicenseError> {
+        if self.domains.is_empty() {
+            return Ok(());
+        }
+        let allowed = host
+            .map(normalize_host)
+            .is_some_and(|host| self.domains.iter().any(|d| domain_matches(d, &host)));
+        if allowed {
+            Ok(())
+        } else {
+            Err(LicenseError::DomainNotAllowed {
+                host: host.map(str::to_string),
+            })
+        }
+    }
+
+    pub fn bind_sources(&self) -> Vec<FingerprintSource> {
+        if self.bind.is_empty() {
+            vec![FingerprintSource::MachineId]
+        } else {
+            self.bind.clone()
+        }
+    }
+
+    pub fn check_host(&self, fingerprint: &str) -> Result<(), LicenseError> {
+        if self.hosts.is_empty() || self.hosts.iter().any(|h| h == fingerprint) {
+            Ok(())
+        } else {
+            Err(LicenseError::HostNotAllowed {
+                fingerprint: fingerprint.to_string(),
+            })
+        }
+    }
+
+    /// 计算本机指纹并检查是否在授权主机列表中；未绑定主机时直接通过
+    pub fn check_current_host(&self, custom: Option<&str>) -> Result<(), LicenseError> {
+        if self.hosts.is_empty() {
+            return Ok(());
+        }
+        let fingerprint = fingerprint::compute(&self.bind_sources(), custom)
+            .map_err(LicenseError::Fingerprint)?;
+        self.check_host(&fingerprint)
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    pub fn status(&self, today: Date) -> LicenseStatus {
+        let Some(expires) = self.expires else {
+            return LicenseStatus::Valid;
+        };
+        let grace_end = expires.days() + self.grace_days as i64;
+        if today <= expires {
+            LicenseStatus::Valid
+        } else if today.days() <= grace_end {
+            LicenseStatus::Grace {
+                days_left: grace_end - today.days(),
+            }
+        } else {
+            LicenseStatus::Expired
+        }
+    }
+
+    pub fn check(&self, today: Date) -> Result<LicenseStatus, LicenseError> {
+        match self.status(today) {
+            LicenseStatus::Expired => Err(LicenseError::Expired {
+                expires: self.expires.unwrap_or(today),
+            }),
+            status => Ok(status),
+        }
+    }
+
+    fn body_lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("customer={}", self.customer)];
+        if let Some(expires) = self.expires {
+            lines.push(format!("expires={}", expires));
+        }
+        lines.push(format!("grace_days={}", self.grace_days));
+        lines.push(format!("features={}", self.features.join(",")));
+        lines.push(format!("key_id={}", self.key_id));
+        if !self.hosts.is_empty() {
+            let bind: Vec<&str> = self.bind_sources().iter().map(|s| s.as_str()).collect();
+            lines.push(format!("bind={}", bind.join(",")));
+            lines.push(format!("hosts={}", self.hosts.join(",")));
+        }
+        if !self.domains.is_empty() {
+            lines.push(format!("domains={}", self.domains.join(",")));
+        }
+        if !self.sapis.is_empty() {
+            lines.push(format!("sapis={}", self.sapis.join(",")));
+        }
+        lines
+    }
+
+    /// 用 `keygen` 生成的 Ed25519 私钥签发授权文件
+    pub fn issue(&self, key: &SigningKey) -> String {
+        let body = self.body_lines();
+        let signature = bytes_to_hex(&signing::sign(key, &signed_message(&body)));
+        format!(
+            "{}\n{}\nsignature={}\n",
+            LICENSE_BANNER,
+            body.join("\n"),
+            signature
+        )
+    }
+
+    /// 解析并用构建时配置的公钥 (`PHP_GUARD_SIGN_PUBKEY`) 校验授权文件
+    pub fn parse(text: &str) -> Result<License, LicenseError> {
+        let key = signing::build_verifying_key()
+            .ok()
+            .flatten()
+            .ok_or(LicenseError::NoPublicKey)?;
+        License::parse_with(text, &key)
+    }
+
+    /// 解析并校验授权文件：签名必须由 `key` 对应的私钥签发，且密钥标识与当前构建一致
+    pub fn parse_with(text: &str, key: &VerifyingKey) -> Result<License, LicenseError> {
+        let mut body = Vec::new();
+        let mut signature = None;
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_prefix("signature=") {
+                Some(value) => signature = Some(value.trim().to_string()),
+                None => body.push(line.to_string()),
+            }
+        }
+
+        let signature = signature.ok_or(LicenseError::MissingField("signature"))?;
+        let valid = hex_to_bytes(&signature)
+            .is_some_and(|signature| signing::verify(key, &signed_message(&body), &signature));
+        if !valid {
+            return Err(LicenseError::BadSignature);
+        }
+
+        let license = License::from_lines(&body)?;
+        let expected = key_id();
+        if license.key_id != expected {
+            return Err(LicenseError::KeyMismatch {
+                expected,
+                found: license.key_id,
+            });
+        }
+
+        Ok(license)
+    }
+
+    fn from_lines(lines: &[String]) -> Result<License, LicenseError> {
+        let mut customer = None;
+        let mut expires = None;
+        let mut grace_days = 0;
+        let mut features = Vec::new();
+        let mut license_key_id = None;
+        let mut bind = Vec::new();
+        let mut hosts = Vec::new();
+        let mut domains = Vec::new();
+        let mut sapis = Vec::new();
+
+        for line in lines {
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| LicenseError::Malformed(line.clone()))?;
+            let value = value.trim();
+            match name.trim() {
+                "customer" => customer = Some(value.to_string()),
+                "expires" => expires = Some(Date::parse(value)?),
+                "grace_days" => {
+                    grace_days = value
+                        .parse()
+                        .map_err(|_| LicenseError::Malformed(line.clone()))?
+                }
+                "features" => features = split_list(value),
+                "key_id" => license_key_id = Some(value.to_string()),
+                "bind" => {
+                    bind = fingerprint::parse_sources(value)
+                        .map_err(|_| LicenseError::Malformed(line.clone()))?
+                }
+                "hosts" => hosts = split_list(value),
+                "domains" => domains = split_list(value),
+                "sapis" => sapis = split_list(value),
+                // 未知字段可能是更新版本加入的限制条件，不能忽略
+                _ => return Err(LicenseError::Malformed(line.clone())),
+            }
+        }
+
+        Ok(License {
+            customer: customer.ok_or(LicenseError::MissingField("customer"))?,
+            expires,
+            grace_days,
+            features,
+            key_id: license_key_id.ok_or(LicenseError::MissingField("key_id"))?,
+            bind,
+            hosts,
+            domains,
+            sapis,
+        })
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// 去掉端口和末尾的点，统一小写
+fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let host = match host.rsplit_once(':') {
+        // IPv6 地址形如 [::1]:8080，只去掉方括号外的端口
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) && !name.ends_with(':') => {
+            name
+        }
+        _ => host,
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn domain_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim().to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => host
+            .strip_suffix(suffix)
+            .is_some_and(|rest| rest.ends_with('.') && rest.len() > 1),
+        None => pattern == host,
+    }
+}
+
+/// 签名覆盖的内容：上下文标记加上每行字段，注释和空行不参与签名
+fn signed_message(body: &[String]) -> Vec<u8> {
+    let mut message = SIGNATURE_CONTEXT.to_vec();
+    for line in body {
+        message.extend_from_slice(line.as_bytes());
+        message.push(b'\n');
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signing_key() -> SigningKey {
+        signing::signing_key_from_hex(&"42".repeat(32)).unwrap()
+    }
+
+    fn issue(license: &License) -> String {
+        license.issue(&signing_key())
+    }
+
+    fn parse(text: &str) -> Result<License, LicenseError> {
+        License::parse_with(text, &signing_key().verifying_key())
+    }
+
+    fn sample() -> License {
+        let mut license = License::new("ACME Inc");
+        license.expires = Date::from_ymd(2027, 1, 31);
+        license.grace_days = 7;
+        license.features = vec!["reports".to_string(), "api".to_string()];
+        license
+    }
+
+    #[test]
+    fn test_issue_and_parse() {
+        let license = sample();
+        let parsed = parse(&issue(&license)).unwrap();
+        assert_eq!(parsed, license);
+        assert!(parsed.has_feature("api"));
+        assert!(!parsed.has_feature("admin"));
+    }
+
+    #[test]
+    fn test_tampered_license_is_rejected() {
+        let text = issue(&sample()).replace("2027-01-31", "2099-01-31");
+        assert_eq!(parse(&text), Err(LicenseError::BadSignature));
+
+        let text = issue(&sample()).replace("features=reports,api", "features=reports,api,admin");
+        assert_eq!(parse(&text), Err(LicenseError::BadSignature));
+
+        // 只有持有私钥的发行方能签发，其他私钥签的授权不被接受
+        let other = signing::signing_key_from_hex(&"43".repeat(32)).unwrap();
+        assert_eq!(
+            parse(&sample().issue(&other)),
+            Err(LicenseError::BadSignature)
+        );
+        let text = issue(&sample());
+        let (body, _) = text.split_once("signature=").unwrap();
+        let forged = format!("{}signature={}\n", body, "00".repeat(64));
+        assert_eq!(parse(&forged), Err(LicenseError::BadSignature));
+    }
+
+    #[test]
+    fn test_status_with_grace_period() {
+        let license = sample();
+        let expires = license.expires.unwrap();
+
+        assert_eq!(license.status(expires), LicenseStatus::Valid);
+        assert_eq!(
+            license.status(Date::from_days(expires.days() + 1)),
+            LicenseStatus::Grace { days_left: 6 }
+        );
+        assert_eq!(
+            license.status(Date::from_days(expires.days() + 7)),
+            LicenseStatus::Grace { days_left: 0 }
+        );
+        assert!(license.check(Date::from_days(expires.days() + 8)).is_err());
+
+        let perpetual = License::new("ACME Inc");
+        assert_eq!(perpetual.status(Date::today()), LicenseStatus::Valid);
+    }
+
+    #[test]
+    fn test_host_binding() {
+        let fingerprint =
+            fingerprint::compute(&[FingerprintSource::Custom], Some("customer-42")).unwrap();
+
+        let mut license = sample();
+        license.bind = vec![FingerprintSource::Custom];
+        license.hosts = vec![fingerprint.clone()];
+
+        let parsed = parse(&issue(&license)).unwrap();
+        assert_eq!(parsed.bind, [FingerprintSource::Custom]);
+        assert!(parsed.check_host(&fingerprint).is_ok());
+        assert!(parsed.check_current_host(Some("customer-42")).is_ok());
+        assert_eq!(
+            parsed.check_current_host(Some("customer-43")),
+            Err(LicenseError::HostNotAllowed {
+                fingerprint: fingerprint::compute(
+                    &[FingerprintSource::Custom],
+                    Some("customer-43")
+                )
+                .unwrap()
+            })
+        );
+
+        assert!(sample().check_current_host(None).is_ok());
+    }
+
+    #[test]
+    fn test_domain_and_sapi_restrictions() {
+        let mut license = sample();
+        license.domains = vec!["example.com".to_string(), "*.example.org".to_string()];
+        license.sapis = vec!["fpm-fcgi".to_string()];
+        let license = parse(&issue(&license)).unwrap();
+
+        assert!(license.check_domain(Some("example.com")).is_ok());
+        assert!(license.check_domain(Some("EXAMPLE.com:8080")).is_ok());
+        assert!(license.check_domain(Some("shop.example.org")).is_ok());
+        assert!(license.check_domain(Some("example.org")).is_err());
+        assert!(license.check_domain(Some("evil-example.com")).is_err());
+        assert!(license.check_domain(Some("example.com.evil.net")).is_err());
+        assert_eq!(
+            license.check_domain(None),
+            Err(LicenseError::DomainNotAllowed { host: None })
+        );
+
+        assert!(license.check_sapi("fpm-fcgi").is_ok());
+        assert_eq!(
+            license.check_sapi("cli"),
+            Err(LicenseError::SapiNotAllowed {
+                sapi: "cli".to_string()
+            })
+        );
+
+        let open = sample();
+        assert!(open.check_domain(None).is_ok());
+        assert!(open.check_sapi("cli").is_ok());
+    }
+
+    #[test]
+    fn test_date_roundtrip() {
+        assert_eq!(Date::parse("1970-01-01").unwrap().days(), 0);
+        assert_eq!(Date::parse("2024-02-29").unwrap().to_string(), "2024-02-29");
+        assert!(Date::parse("2023-02-29").is_err());
+        assert!(Date::parse("2023-13-01").is_err());
+        assert!(Date::parse("tomorrow").is_err());
+
+        let date = Date::from_ymd(2026, 10, 19).unwrap();
+        assert_eq!(Date::from_days(date.days()), date);
+    }
+}
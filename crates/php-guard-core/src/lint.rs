@@ -0,0 +1,136 @@
+//! 加密前的分隔符检查。
+//!
+//! 这不是语法检查：只在词法与括号结构层面找出未闭合的字符串、注释、heredoc，以及不匹配的
+//! `()`、`[]`、`{}`、`#[]`，缺少分号等语法错误不会被发现。完整的语法检查需要 `php -l`。
+
+use std::fmt;
+
+use crate::lexer::{Token, TokenKind, tokenize};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub line: usize,
+    /// 从 1 开始的字节列号
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+fn error_at(src: &[u8], token: &Token, message: String) -> SyntaxError {
+    let line_start = src[..token.offset]
+        .iter()
+        .rposition(|&c| c == b'\n')
+        .map_or(0, |i| i + 1);
+    SyntaxError {
+        line: token.line,
+        column: token.offset - line_start + 1,
+        message,
+    }
+}
+
+fn describe(kind: TokenKind) -> &'static str {
+    match kind {
+        TokenKind::BlockComment | TokenKind::DocComment => "comment",
+        TokenKind::Heredoc => "heredoc",
+        TokenKind::Nowdoc => "nowdoc",
+        _ => "string",
+    }
+}
+
+pub fn check_delimiters(src: &[u8]) -> Result<(), SyntaxError> {
+    let tokens = tokenize(src);
+    let mut open: Vec<(&Token, u8)> = Vec::new();
+
+    for token in &tokens {
+        if !token.terminated {
+            return Err(error_at(
+                src,
+                token,
+                format!("unterminated {}", describe(token.kind)),
+            ));
+        }
+
+        let expected = match token.kind {
+            TokenKind::AttributeStart => {
+                open.push((token, b']'));
+                continue;
+            }
+            TokenKind::Punct => match token.text[0] {
+                b'(' => Some(b')'),
+                b'[' => Some(b']'),
+                b'{' => Some(b'}'),
+                _ => None,
+            },
+            _ => continue,
+        };
+        if let Some(close) = expected {
+            open.push((token, close));
+            continue;
+        }
+
+        let c = token.text[0];
+        if !matches!(c, b')' | b']' | b'}') {
+            continue;
+        }
+        match open.pop() {
+            Some((_, expected)) if expected == c => {}
+            Some((_, expected)) => {
+                return Err(error_at(
+                    src,
+                    token,
+                    format!(
+                        "unexpected '{}', expected '{}'",
+                        c as char, expected as char
+                    ),
+                ));
+            }
+            None => {
+                return Err(error_at(src, token, format!("unexpected '{}'", c as char)));
+            }
+        }
+    }
+
+    match open.pop() {
+        Some((token, _)) => Err(error_at(
+            src,
+            token,
+            format!("unclosed '{}'", String::from_utf8_lossy(token.text)),
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(src: &str) -> String {
+        check_delimiters(src.as_bytes()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_valid_source() {
+        let src = "<?php\n#[Route('/')]\nfunction f($a) { return \"{$a['}']}\" . <<<EOT\n)\nEOT; }\n?>\n<div><?php if ($x) { ?>html<?php } ?></div>\n";
+        assert!(check_delimiters(src.as_bytes()).is_ok());
+        assert!(check_delimiters(b"<?php __halt_compiler(); ((( '").is_ok());
+    }
+
+    #[test]
+    fn test_reports_position() {
+        assert_eq!(
+            error("<?php\nif ($a) {\n    foo(1];\n}\n"),
+            "3:10: unexpected ']', expected ')'"
+        );
+        assert_eq!(error("<?php\n}\n"), "2:1: unexpected '}'");
+        assert_eq!(error("<?php\nfunction f() {\n"), "2:14: unclosed '{'");
+        assert_eq!(error("<?php\n$a = 'x;\n"), "2:6: unterminated string");
+        assert_eq!(error("<?php /* a\n"), "1:7: unterminated comment");
+    }
+}
@@ -0,0 +1,170 @@
+//! 明文和密钥在内存中的保护。
+//!
+//! 解密得到的源码在释放前清零，不会残留在已释放的堆内存或 core dump 中；可选用
+//! `mlock` 锁定，防止被换出到交换分区。头部、密钥标识等比较使用常数时间。
+
+use std::fmt;
+use std::io::{self, Read};
+use std::ops::Deref;
+
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, Zeroizing};
+
+/// 常数时间比较；长度不同直接返回 `false`，长度本身不是秘密
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+pub fn ct_starts_with(data: &[u8], prefix: &[u8]) -> bool {
+    data.len() >= prefix.len() && ct_eq(&data[..prefix.len()], prefix)
+}
+
+#[cfg(unix)]
+fn mlock(data: &Vec<u8>) -> bool {
+    let len = data.capacity();
+    len == 0 || unsafe { libc::mlock(data.as_ptr().cast(), len) == 0 }
+}
+
+#[cfg(unix)]
+fn munlock(data: &Vec<u8>) {
+    let len = data.capacity();
+    if len > 0 {
+        unsafe {
+            libc::munlock(data.as_ptr().cast(), len);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn mlock(_data: &Vec<u8>) -> bool {
+    false
+}
+
+#[cfg(not(unix))]
+fn munlock(_data: &Vec<u8>) {}
+
+/// 解密得到的明文，drop 时清零整个分配 (包括未使用的容量)
+pub struct SecretBytes {
+    data: Vec<u8>,
+    locked: bool,
+}
+
+impl SecretBytes {
+    pub fn new(data: Vec<u8>) -> SecretBytes {
+        SecretBytes {
+            data,
+            locked: false,
+        }
+    }
+
+    /// 用 `mlock` 锁定内存页。受 `RLIMIT_MEMLOCK` 限制可能失败，失败时返回 `false`，数据仍可正常使用
+    pub fn lock(&mut self) -> bool {
+        if !self.locked {
+            self.locked = mlock(&self.data);
+        }
+        self.locked
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// 追加内容；需要扩容时先复制到新分配，再清零旧分配
+    fn extend_from_slice(&mut self, bytes: &[u8]) {
+        let needed = self.data.len() + bytes.len();
+        if needed > self.data.capacity() {
+            let mut grown = Vec::with_capacity(needed.max(self.data.capacity() * 2));
+            grown.extend_from_slice(&self.data);
+            let mut old = std::mem::replace(&mut self.data, grown);
+            old.zeroize();
+            if self.locked {
+                munlock(&old);
+                self.locked = mlock(&self.data);
+            }
+        }
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.data.zeroize();
+        if self.locked {
+            munlock(&self.data);
+        }
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl AsRef<[u8]> for SecretBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// 不输出内容
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretBytes")
+            .field("len", &self.data.len())
+            .field("locked", &self.locked)
+            .finish()
+    }
+}
+
+/// 与 `Read::read_to_end` 相同，但扩容时清零旧缓冲区。
+///
+/// 解压器内部的窗口缓冲区不在此列，由各自的实现释放。
+pub(crate) fn read_to_end<R: Read>(mut reader: R, size_hint: usize) -> io::Result<SecretBytes> {
+    let mut out = SecretBytes::new(Vec::with_capacity(size_hint));
+    let mut chunk = Zeroizing::new([0u8; 8192]);
+    loop {
+        match reader.read(&mut chunk[..]) {
+            Ok(0) => return Ok(out),
+            Ok(n) => out.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ct_compare() {
+        assert!(ct_eq(b"abc", b"abc"));
+        assert!(!ct_eq(b"abc", b"abd"));
+        assert!(!ct_eq(b"abc", b"ab"));
+        assert!(ct_starts_with(b"<?php bin", b"<?php"));
+        assert!(!ct_starts_with(b"<?", b"<?php"));
+    }
+
+    #[test]
+    fn test_read_to_end_grows() {
+        let data: Vec<u8> = (0..50_000u32).map(|i| i as u8).collect();
+        let mut secret = read_to_end(&data[..], 16).unwrap();
+        assert_eq!(secret.as_slice(), &data[..]);
+
+        // 锁定可能因资源限制失败，但不能影响数据
+        secret.lock();
+        secret.extend_from_slice(&data);
+        assert_eq!(secret.len(), data.len() * 2);
+    }
+}
@@ -0,0 +1,56 @@
+use ed25519_dalek::{Signature, Signer, Verifier};
+
+pub use ed25519_dalek::{SIGNATURE_LENGTH, SigningKey, VerifyingKey};
+
+use crate::crypto::{bytes_to_hex, hex_to_bytes};
+
+pub fn generate_signing_key() -> std::io::Result<SigningKey> {
+    let mut seed = [0u8; 32];
+    getrandom::fill(&mut seed).map_err(|e| std::io::Error::other(e.to_string()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// 私钥文件内容为 32 字节种子的十六进制
+pub fn signing_key_from_hex(hex: &str) -> Result<SigningKey, String> {
+    let bytes = hex_to_bytes(hex.trim()).ok_or("signing key is not valid hex")?;
+    let seed: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| "signing key must be 32 bytes")?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+pub fn signing_key_to_hex(key: &SigningKey) -> String {
+    bytes_to_hex(key.as_bytes())
+}
+
+pub fn verifying_key_from_bytes(bytes: &[u8]) -> Result<VerifyingKey, String> {
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| "public key must be 32 bytes")?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| e.to_string())
+}
+
+pub fn verifying_key_to_hex(key: &VerifyingKey) -> String {
+    bytes_to_hex(key.as_bytes())
+}
+
+pub fn sign(key: &SigningKey, data: &[u8]) -> [u8; SIGNATURE_LENGTH] {
+    key.sign(data).to_bytes()
+}
+
+pub fn verify(key: &VerifyingKey, data: &[u8], signature: &[u8]) -> bool {
+    match Signature::from_slice(signature) {
+        Ok(signature) => key.verify(data, &signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// 构建时配置的公钥 (`PHP_GUARD_SIGN_PUBKEY`)，设置后扩展只运行签名有效的文件。
+///
+/// 配置了但无法解析时返回错误而不是 `None`，调用方不能因此跳过签名校验。
+pub fn build_verifying_key() -> crate::Result<Option<VerifyingKey>> {
+    crate::config::SIGN_PUBLIC_KEY
+        .map(|bytes| verifying_key_from_bytes(&bytes))
+        .transpose()
+        .map_err(crate::Error::Config)
+}
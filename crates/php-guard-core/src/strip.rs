@@ -0,0 +1,136 @@
+//! 加密前去除注释和多余空白，效果类似 `php -w`。
+//!
+//! 字符串、heredoc、内联 HTML 以及 `__halt_compiler()` 之后的数据原样保留。
+
+use crate::lexer::{Token, TokenKind, tokenize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StripOptions {
+    /// 用换行代替被删除内容中的换行，错误信息和堆栈中的行号保持不变
+    pub keep_lines: bool,
+    /// 保留含 `@` 标签的文档注释，供依赖反射读取注解的框架使用
+    pub keep_annotations: bool,
+}
+
+fn is_annotation(token: &Token) -> bool {
+    token.kind == TokenKind::DocComment && token.text.contains(&b'@')
+}
+
+/// 这些字符与任何相邻记号都不会粘连，两侧的空白可以整体删除
+fn is_separator(c: u8) -> bool {
+    matches!(c, b';' | b',' | b'{' | b'}' | b'(' | b')' | b'[' | b']')
+}
+
+pub fn strip(src: &[u8], options: &StripOptions) -> Vec<u8> {
+    let mut out = Vec::with_capacity(src.len());
+    let mut pending_space = false;
+    let mut pending_newlines = 0usize;
+    // heredoc 结束标记后的换行在 PHP 7.3 之前是必需的
+    let mut after_heredoc = false;
+    // PHP 8 之前 `#[` 开始的是行注释，属性的 `]` 之后必须换行，否则同一行的后续代码被注释掉
+    let mut attribute_depth = 0usize;
+    let mut after_attribute = false;
+
+    for token in tokenize(src) {
+        let removed = match token.kind {
+            TokenKind::Whitespace | TokenKind::LineComment | TokenKind::BlockComment => true,
+            TokenKind::DocComment => !(options.keep_annotations && is_annotation(&token)),
+            _ => false,
+        };
+
+        if removed {
+            let newlines = token.text.iter().filter(|&&c| c == b'\n').count();
+            pending_space = true;
+            if options.keep_lines {
+                pending_newlines += newlines;
+            } else if after_heredoc && newlines > 0 {
+                pending_newlines = 1;
+            }
+            continue;
+        }
+
+        if pending_newlines > 0 {
+            out.extend(std::iter::repeat_n(b'\n', pending_newlines));
+        } else if after_attribute {
+            out.push(b'\n');
+        } else if pending_space
+            && let (Some(&last), Some(&next)) = (out.last(), token.text.first())
+            && !last.is_ascii_whitespace()
+            && !is_separator(last)
+            && !is_separator(next)
+        {
+            out.push(b' ');
+        }
+        pending_space = false;
+        pending_newlines = 0;
+        after_heredoc = matches!(token.kind, TokenKind::Heredoc | TokenKind::Nowdoc);
+        after_attribute = false;
+        if token.kind == TokenKind::AttributeStart || (attribute_depth > 0 && token.is_punct(b'['))
+        {
+            attribute_depth += 1;
+        } else if attribute_depth > 0 && token.is_punct(b']') {
+            attribute_depth -= 1;
+            after_attribute = attribute_depth == 0;
+        }
+
+        out.extend_from_slice(token.text);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "<?php\n\
+        /**\n * Service.\n * @Route(\"/a\")\n */\n\
+        #[Attr(1)]\n\
+        function  f( $a ,  $b ) {   // sum\n\
+        \x20   /* block\n       comment */\n\
+        \x20   return $a + +$b . '  // kept  ';\n\
+        }\n\
+        $s = <<<EOT\n  text # kept\n  EOT;\n\
+        __halt_compiler();  // data\n";
+
+    #[test]
+    fn test_strip_compact() {
+        let out = strip(SOURCE.as_bytes(), &StripOptions::default());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "<?php\n#[Attr(1)]\nfunction f($a,$b){return $a + +$b . '  // kept  ';}$s = <<<EOT\n  text # kept\n  EOT;__halt_compiler();  // data\n"
+        );
+    }
+
+    #[test]
+    fn test_strip_keeps_newline_after_attribute() {
+        // PHP 7 把 `#[` 当作行注释，属性和后面的代码不能合并到同一行
+        let source = "<?php\nclass A {\n #[\\ReturnTypeWillChange]\n public function j() { return [1, [2]]; }\n \
+            #[A([1, 2]), B] #[C] public $p;\n}\n";
+        let out = String::from_utf8(strip(source.as_bytes(), &StripOptions::default())).unwrap();
+        assert_eq!(
+            out,
+            "<?php\nclass A{#[\\ReturnTypeWillChange]\npublic function j(){return[1,[2]];}\
+            #[A([1,2]),B]\n#[C]\npublic $p;}"
+        );
+        for line in out.lines().filter(|line| line.contains("#[")) {
+            assert!(line.trim_end().ends_with(']'), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_strip_keep_lines_and_annotations() {
+        let options = StripOptions {
+            keep_lines: true,
+            keep_annotations: true,
+        };
+        let out = String::from_utf8(strip(SOURCE.as_bytes(), &options)).unwrap();
+        assert_eq!(out.lines().count(), SOURCE.lines().count());
+        assert!(out.contains("@Route(\"/a\")"));
+        assert!(!out.contains("sum"));
+        assert!(!out.contains("block"));
+
+        let return_line = |s: &str| s.lines().position(|l| l.contains("return")).unwrap();
+        assert_eq!(return_line(&out), return_line(SOURCE));
+    }
+}
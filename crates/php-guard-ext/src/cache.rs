@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use php_guard_core::SecretBytes;
+
+/// 文件身份：路径相同但 mtime/inode/size 任一变化都视为新文件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub inode: u64,
+    pub size: u64,
+}
+
+impl FileStamp {
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        Self {
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            inode: metadata.ino(),
+            size: metadata.size(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: u64,
+}
+
+/// 链表的空指针
+const NIL: usize = usize::MAX;
+
+struct Node {
+    path: String,
+    stamp: FileStamp,
+    /// 条目被淘汰或失效且没有其他引用时清零
+    source: Arc<SecretBytes>,
+    prev: usize,
+    next: usize,
+}
+
+/// 条目按使用顺序串成双向链表，链首最近使用、链尾最久未使用。
+///
+/// 节点存放在 `nodes` 的槽位中，用下标互相链接，删除后的槽位记在 `free` 中复用；
+/// 查找、移到链首和淘汰都是 O(1)。
+struct Inner {
+    index: HashMap<String, usize>,
+    nodes: Vec<Option<Node>>,
+    free: Vec<usize>,
+    head: usize,
+    tail: usize,
+}
+
+impl Inner {
+    fn new() -> Self {
+        Self {
+            index: HashMap::new(),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: NIL,
+            tail: NIL,
+        }
+    }
+
+    fn node(&self, i: usize) -> &Node {
+        self.nodes[i].as_ref().expect("linked slot is occupied")
+    }
+
+    fn node_mut(&mut self, i: usize) -> &mut Node {
+        self.nodes[i].as_mut().expect("linked slot is occupied")
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let Node { prev, next, .. } = *self.node(i);
+        match prev {
+            NIL => self.head = next,
+            prev => self.node_mut(prev).next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.node_mut(next).prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        let head = self.head;
+        let node = self.node_mut(i);
+        node.prev = NIL;
+        node.next = head;
+        match head {
+            NIL => self.tail = i,
+            head => self.node_mut(head).prev = i,
+        }
+        self.head = i;
+    }
+
+    fn remove(&mut self, i: usize) {
+        self.unlink(i);
+        if let Some(node) = self.nodes[i].take() {
+            self.index.remove(&node.path);
+        }
+        self.free.push(i);
+    }
+
+    fn push(&mut self, node: Node) {
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.nodes[i] = Some(node);
+                i
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+        self.index.insert(self.node(i).path.clone(), i);
+        self.push_front(i);
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+}
+
+pub struct SourceCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl SourceCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Inner::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// 查找解密结果。未命中 (包括文件已变化) 在这里计数，之后是否解密成功、是否插入都不影响统计。
+    pub fn get(&self, path: &str, stamp: &FileStamp) -> Option<Arc<SecretBytes>> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+
+        let found = match inner.index.get(path) {
+            Some(&i) if inner.node(i).stamp == *stamp => Some(i),
+            Some(&i) => {
+                // 文件已被替换，旧的解密结果立即丢弃
+                inner.remove(i);
+                None
+            }
+            None => None,
+        };
+
+        let Some(i) = found else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        inner.unlink(i);
+        inner.push_front(i);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(Arc::clone(&inner.node(i).source))
+    }
+
+    pub fn insert(&self, path: &str, stamp: FileStamp, source: Arc<SecretBytes>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(&i) = inner.index.get(path) {
+            inner.remove(i);
+        }
+        if inner.len() >= self.capacity {
+            let oldest = inner.tail;
+            inner.remove(oldest);
+        }
+
+        inner.push(Node {
+            path: path.to_string(),
+            stamp,
+            source,
+            prev: NIL,
+            next: NIL,
+        });
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: inner.len() as u64,
+        }
+    }
+}
+
+static CACHE: OnceLock<SourceCache> = OnceLock::new();
+
+/// 在 MINIT 中根据 `php_guard.cache_size` 初始化，0 表示关闭缓存
+pub fn init(capacity: usize) {
+    if capacity > 0 {
+        let _ = CACHE.set(SourceCache::new(capacity));
+    }
+}
+
+pub fn global() -> Option<&'static SourceCache> {
+    CACHE.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(mtime: i64) -> FileStamp {
+        FileStamp {
+            mtime,
+            mtime_nsec: 0,
+            inode: 1,
+            size: 10,
+        }
+    }
+
+    fn source(content: &[u8]) -> Arc<SecretBytes> {
+        Arc::new(SecretBytes::new(content.to_vec()))
+    }
+
+    #[test]
+    fn test_hit_and_miss() {
+        let cache = SourceCache::new(2);
+        assert!(cache.get("/a.php", &stamp(1)).is_none());
+
+        cache.insert("/a.php", stamp(1), source(b"a"));
+        assert_eq!(cache.get("/a.php", &stamp(1)).unwrap().as_slice(), b"a");
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.entries, 1);
+    }
+
+    #[test]
+    fn test_changed_file_is_invalidated() {
+        let cache = SourceCache::new(2);
+        cache.insert("/a.php", stamp(1), source(b"old"));
+
+        assert!(cache.get("/a.php", &stamp(2)).is_none());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = SourceCache::new(2);
+        cache.insert("/a.php", stamp(1), source(b"a"));
+        cache.insert("/b.php", stamp(1), source(b"b"));
+
+        // 访问 a 之后，b 成为最久未使用
+        assert!(cache.get("/a.php", &stamp(1)).is_some());
+        cache.insert("/c.php", stamp(1), source(b"c"));
+
+        assert!(cache.get("/a.php", &stamp(1)).is_some());
+        assert!(cache.get("/b.php", &stamp(1)).is_none());
+        assert!(cache.get("/c.php", &stamp(1)).is_some());
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn test_lookup_misses_are_counted() {
+        let cache = SourceCache::new(2);
+        // 未插入的查找 (未加密或解密失败的文件) 同样计为未命中
+        assert!(cache.get("/plain.php", &stamp(1)).is_none());
+        assert!(cache.get("/broken.php", &stamp(1)).is_none());
+
+        cache.insert("/a.php", stamp(1), source(b"a"));
+        assert!(cache.get("/a.php", &stamp(2)).is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (0, 3, 0));
+    }
+
+    #[test]
+    fn test_eviction_order_with_reused_slots() {
+        let cache = SourceCache::new(3);
+        for n in 0..10 {
+            let path = format!("/{}.php", n);
+            cache.insert(&path, stamp(1), source(path.as_bytes()));
+            // 每次都访问 0，它一直是最近使用的
+            assert!(cache.get("/0.php", &stamp(1)).is_some());
+        }
+
+        assert_eq!(cache.stats().entries, 3);
+        for present in ["/0.php", "/8.php", "/9.php"] {
+            assert!(cache.get(present, &stamp(1)).is_some(), "{}", present);
+        }
+        assert!(cache.get("/7.php", &stamp(1)).is_none());
+
+        // 重复插入同一路径只保留一个条目
+        cache.insert("/8.php", stamp(2), source(b"new"));
+        assert_eq!(cache.stats().entries, 3);
+        assert_eq!(cache.get("/8.php", &stamp(2)).unwrap().as_slice(), b"new");
+    }
+}
@@ -0,0 +1,532 @@
+//! 编译钩子的决策逻辑，与 Zend 无关。
+//!
+//! `php_guard_compile_file` 只负责取出文件名并执行 [`run`] 的结果 (把明文作为内存流
+//! 交给原始函数、抛出异常、调用原始函数)。是否解密、opcache/授权/调试器检查、缓存和解密都在这里完成，
+//! 依赖的运行环境通过 [`CompileBackend`] 提供，测试中用模拟实现代替 PHP。
+//!
+//! OPcache 命中时不会调用我们的编译钩子，授权和调试器检查因此还由位于 OPcache 之前的
+//! 守卫钩子执行，见 [`guard`]。
+
+use std::sync::Arc;
+
+use php_guard_core::{Decryptor, SecretBytes, check_file_encrypted, is_encrypted};
+use zeroize::Zeroizing;
+
+use crate::cache::{FileStamp, SourceCache};
+use crate::state::{self, Event};
+
+/// 编译钩子依赖的运行环境
+pub(crate) trait CompileBackend {
+    /// MSHUTDOWN 之后钩子可能仍在链中，此时保持直通
+    fn hook_active(&self) -> bool;
+    /// `opcache.file_cache` 是否生效
+    fn file_cache_active(&self) -> bool;
+    /// 是否配置了授权或调试器策略，都没有时守卫钩子不检查文件头
+    fn checks_enabled(&self) -> bool;
+    /// 授权检查：`Ok(Some(_))` 为需要发出的警告，`Err` 为拒绝原因
+    fn check_license(&self) -> Result<Option<String>, String>;
+    /// 调试器检查，返回值含义同 [`CompileBackend::check_license`]
+    fn check_debugger(&self) -> Result<Option<String>, String>;
+    fn warn(&self, message: &str);
+    fn decryptor(&self) -> php_guard_core::Result<&Decryptor>;
+    fn cache(&self) -> Option<&SourceCache>;
+}
+
+#[derive(Debug)]
+pub(crate) enum Outcome {
+    /// 不属于我们处理的文件，直接交给原始函数，不计数
+    Original,
+    /// 未加密文件，交给原始函数
+    Passthrough,
+    /// 完整解密并校验通过的源码，作为内存流交给原始函数编译，不落盘
+    Decrypted(Arc<SecretBytes>),
+    /// 抛出异常并中止编译
+    Refuse { message: String, code: i32 },
+}
+
+fn should_decrypt(filename: &str) -> bool {
+    if filename == "-" {
+        return false;
+    }
+    if filename.starts_with("phar:") {
+        return false;
+    }
+    true
+}
+
+/// 授权和调试器检查，警告直接发出，`Err` 为拒绝原因
+fn check_policies<B: CompileBackend>(backend: &B) -> Result<(), String> {
+    for check in [B::check_license, B::check_debugger] {
+        if let Some(warning) = check(backend)? {
+            backend.warn(&warning);
+        }
+    }
+    Ok(())
+}
+
+/// 读取并解密文件。未加密返回 `Ok(None)`；签名校验失败、文件损坏等返回错误。
+fn read_decrypted(
+    decryptor: &Decryptor,
+    filename: &str,
+) -> php_guard_core::Result<Option<SecretBytes>> {
+    let content = Zeroizing::new(std::fs::read(filename)?);
+    if !is_encrypted(&content) {
+        return Ok(None);
+    }
+    decryptor.decrypt(&content).map(Some)
+}
+
+fn load_cached(
+    cache: &SourceCache,
+    decryptor: &Decryptor,
+    filename: &str,
+) -> php_guard_core::Result<Option<Arc<SecretBytes>>> {
+    let metadata = std::fs::metadata(filename)?;
+    let stamp = FileStamp::from_metadata(&metadata);
+    if let Some(source) = cache.get(filename, &stamp) {
+        return Ok(Some(source));
+    }
+
+    let Some(source) = read_decrypted(decryptor, filename)? else {
+        return Ok(None);
+    };
+    let source = Arc::new(source);
+    cache.insert(filename, stamp, Arc::clone(&source));
+    Ok(Some(source))
+}
+
+/// 在内存中完整解密，返回 `Ok(None)` 表示文件未加密。
+///
+/// 分块容器的所有块都校验通过后才返回明文，校验失败时已解密的部分随缓冲区清零，
+/// 不会有任何明文交给 PHP。
+fn decrypt_source<B: CompileBackend>(
+    backend: &B,
+    filename: &str,
+) -> php_guard_core::Result<Option<Arc<SecretBytes>>> {
+    let decryptor = backend.decryptor()?;
+    match backend.cache() {
+        Some(cache) => load_cached(cache, decryptor, filename),
+        None => Ok(read_decrypted(decryptor, filename)?.map(Arc::new)),
+    }
+}
+
+/// 决定如何编译 `filename`；`None` 表示文件句柄没有可用的文件名
+pub(crate) fn process<B: CompileBackend>(backend: &B, filename: Option<&str>) -> Outcome {
+    if !backend.hook_active() {
+        return Outcome::Original;
+    }
+    let Some(filename) = filename.filter(|name| should_decrypt(name)) else {
+        return Outcome::Original;
+    };
+
+    if !check_file_encrypted(filename).unwrap_or(false) {
+        return Outcome::Passthrough;
+    }
+
+    if backend.file_cache_active() {
+        return Outcome::Refuse {
+            message: format!(
+                "refusing to compile protected file {} while opcache.file_cache is enabled",
+                filename
+            ),
+            code: 0,
+        };
+    }
+
+    if let Err(message) = check_policies(backend) {
+        return Outcome::Refuse { message, code: 0 };
+    }
+
+    match decrypt_source(backend, filename) {
+        Ok(Some(source)) => Outcome::Decrypted(source),
+        // 检查头部之后文件被替换成了明文
+        Ok(None) => Outcome::Passthrough,
+        Err(e) => Outcome::Refuse {
+            message: format!("{}: {}", filename, e),
+            code: e.code(),
+        },
+    }
+}
+
+/// 编译钩子的入口：决定如何编译 `filename` 并计入解密统计
+pub(crate) fn run<B: CompileBackend>(backend: &B, filename: Option<&str>) -> Outcome {
+    let outcome = process(backend, filename);
+    match outcome {
+        Outcome::Original => {}
+        Outcome::Passthrough => state::record(Event::Passthrough),
+        Outcome::Decrypted(_) => state::record(Event::Decrypted),
+        Outcome::Refuse { .. } => state::record(Event::Failed),
+    }
+    outcome
+}
+
+/// 守卫钩子的决策：每次 include 受保护文件 (包括 OPcache 命中) 都执行授权和调试器检查。
+///
+/// 只返回 [`Outcome::Original`] (交给链中的下一环) 或 [`Outcome::Refuse`]；
+/// 解密仍由 [`process`] 在缓存未命中时完成。
+pub(crate) fn guard<B: CompileBackend>(backend: &B, filename: Option<&str>) -> Outcome {
+    if !backend.hook_active() || !backend.checks_enabled() {
+        return Outcome::Original;
+    }
+    let Some(filename) = filename.filter(|name| should_decrypt(name)) else {
+        return Outcome::Original;
+    };
+    if !check_file_encrypted(filename).unwrap_or(false) {
+        return Outcome::Original;
+    }
+
+    match check_policies(backend) {
+        Ok(()) => Outcome::Original,
+        Err(message) => Outcome::Refuse { message, code: 0 },
+    }
+}
+
+/// 代替 PHP 运行环境的 [`CompileBackend`]，记录被调用的检查和发出的警告
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    use php_guard_core::Error;
+
+    pub(crate) struct MockBackend {
+        pub(crate) active: bool,
+        pub(crate) file_cache: bool,
+        pub(crate) checks_enabled: bool,
+        pub(crate) license: Result<Option<String>, String>,
+        pub(crate) debugger: Result<Option<String>, String>,
+        pub(crate) decryptor: php_guard_core::Result<Decryptor>,
+        pub(crate) cache: Option<SourceCache>,
+        pub(crate) warnings: RefCell<Vec<String>>,
+        pub(crate) checks: RefCell<Vec<&'static str>>,
+    }
+
+    impl Default for MockBackend {
+        fn default() -> Self {
+            MockBackend {
+                active: true,
+                file_cache: false,
+                checks_enabled: true,
+                license: Ok(None),
+                debugger: Ok(None),
+                decryptor: Ok(Decryptor::default()),
+                cache: None,
+                warnings: RefCell::new(Vec::new()),
+                checks: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl CompileBackend for MockBackend {
+        fn hook_active(&self) -> bool {
+            self.active
+        }
+
+        fn file_cache_active(&self) -> bool {
+            self.file_cache
+        }
+
+        fn checks_enabled(&self) -> bool {
+            self.checks_enabled
+        }
+
+        fn check_license(&self) -> Result<Option<String>, String> {
+            self.checks.borrow_mut().push("license");
+            self.license.clone()
+        }
+
+        fn check_debugger(&self) -> Result<Option<String>, String> {
+            self.checks.borrow_mut().push("debugger");
+            self.debugger.clone()
+        }
+
+        fn warn(&self, message: &str) {
+            self.warnings.borrow_mut().push(message.to_string());
+        }
+
+        fn decryptor(&self) -> php_guard_core::Result<&Decryptor> {
+            self.decryptor.as_ref().map_err(Error::clone)
+        }
+
+        fn cache(&self) -> Option<&SourceCache> {
+            self.cache.as_ref()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockBackend;
+    use super::*;
+
+    use std::path::Path;
+
+    use php_guard_core::{Algorithm, Encryptor, Error, Key, encrypt_content};
+
+    const SECRET: &[u8] = b"<?php echo 'secret';";
+
+    fn write(dir: &Path, name: &str, content: &[u8]) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn compiled_source(outcome: Outcome) -> Vec<u8> {
+        match outcome {
+            Outcome::Decrypted(source) => source.to_vec(),
+            other => panic!("expected decrypted source, got {:?}", other),
+        }
+    }
+
+    fn refused(outcome: Outcome) -> (String, i32) {
+        match outcome {
+            Outcome::Refuse { message, code } => (message, code),
+            other => panic!("expected refusal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_untouched_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = write(dir.path(), "plain.php", b"<?php echo 1;");
+        let encrypted = write(dir.path(), "secret.php", &encrypt_content(SECRET).unwrap());
+        let backend = MockBackend::default();
+
+        assert!(matches!(process(&backend, None), Outcome::Original));
+        assert!(matches!(process(&backend, Some("-")), Outcome::Original));
+        assert!(matches!(
+            process(&backend, Some("phar:///app.phar/index.php")),
+            Outcome::Original
+        ));
+        assert!(matches!(
+            process(&backend, Some(&plain)),
+            Outcome::Passthrough
+        ));
+        assert!(matches!(
+            process(&backend, Some("/missing/file.php")),
+            Outcome::Passthrough
+        ));
+
+        let inactive = MockBackend {
+            active: false,
+            ..MockBackend::default()
+        };
+        assert!(matches!(
+            process(&inactive, Some(&encrypted)),
+            Outcome::Original
+        ));
+        assert!(backend.checks.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_decrypts_protected_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let encrypted = write(dir.path(), "secret.php", &encrypt_content(SECRET).unwrap());
+        let backend = MockBackend::default();
+
+        assert_eq!(compiled_source(process(&backend, Some(&encrypted))), SECRET);
+        assert_eq!(*backend.checks.borrow(), ["license", "debugger"]);
+        assert!(backend.warnings.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_policy_refusals() {
+        let dir = tempfile::tempdir().unwrap();
+        let encrypted = write(dir.path(), "secret.php", &encrypt_content(SECRET).unwrap());
+
+        let file_cache = MockBackend {
+            file_cache: true,
+            ..MockBackend::default()
+        };
+        let (message, code) = refused(process(&file_cache, Some(&encrypted)));
+        assert!(message.contains("opcache.file_cache") && message.contains(&encrypted));
+        assert_eq!(code, 0);
+        assert!(file_cache.checks.borrow().is_empty());
+
+        // 授权失败时不再检查调试器
+        let unlicensed = MockBackend {
+            license: Err("license expired".to_string()),
+            ..MockBackend::default()
+        };
+        assert_eq!(
+            refused(process(&unlicensed, Some(&encrypted))),
+            ("license expired".to_string(), 0)
+        );
+        assert_eq!(*unlicensed.checks.borrow(), ["license"]);
+
+        let debugged = MockBackend {
+            debugger: Err("debugger loaded (xdebug)".to_string()),
+            ..MockBackend::default()
+        };
+        assert_eq!(refused(process(&debugged, Some(&encrypted))).1, 0);
+
+        let warned = MockBackend {
+            license: Ok(Some("grace period".to_string())),
+            debugger: Ok(Some("debugger loaded".to_string())),
+            ..MockBackend::default()
+        };
+        assert_eq!(compiled_source(process(&warned, Some(&encrypted))), SECRET);
+        assert_eq!(
+            *warned.warnings.borrow(),
+            ["grace period", "debugger loaded"]
+        );
+    }
+
+    #[test]
+    fn test_guard_checks_every_include() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = write(dir.path(), "plain.php", b"<?php echo 1;");
+        let encrypted = write(dir.path(), "secret.php", &encrypt_content(SECRET).unwrap());
+
+        // 没有配置授权和调试器策略时不读取文件头
+        let disabled = MockBackend {
+            checks_enabled: false,
+            license: Err("license expired".to_string()),
+            ..MockBackend::default()
+        };
+        assert!(matches!(
+            guard(&disabled, Some(&encrypted)),
+            Outcome::Original
+        ));
+        assert!(disabled.checks.borrow().is_empty());
+
+        let unlicensed = MockBackend {
+            license: Err("license expired".to_string()),
+            ..MockBackend::default()
+        };
+        assert!(matches!(
+            guard(&unlicensed, Some(&plain)),
+            Outcome::Original
+        ));
+        assert!(matches!(guard(&unlicensed, None), Outcome::Original));
+        assert!(unlicensed.checks.borrow().is_empty());
+
+        // OPcache 命中时只经过守卫钩子，每次 include 都要检查
+        for _ in 0..2 {
+            assert_eq!(
+                refused(guard(&unlicensed, Some(&encrypted))),
+                ("license expired".to_string(), 0)
+            );
+        }
+        assert_eq!(*unlicensed.checks.borrow(), ["license", "license"]);
+
+        let debugged = MockBackend {
+            debugger: Err("debugger loaded (xdebug)".to_string()),
+            ..MockBackend::default()
+        };
+        assert_eq!(
+            refused(guard(&debugged, Some(&encrypted))),
+            ("debugger loaded (xdebug)".to_string(), 0)
+        );
+
+        let warned = MockBackend {
+            debugger: Ok(Some("debugger loaded".to_string())),
+            ..MockBackend::default()
+        };
+        assert!(matches!(
+            guard(&warned, Some(&encrypted)),
+            Outcome::Original
+        ));
+        assert_eq!(*warned.checks.borrow(), ["license", "debugger"]);
+        assert_eq!(*warned.warnings.borrow(), ["debugger loaded"]);
+    }
+
+    #[test]
+    fn test_decrypt_errors_carry_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let encrypted = write(dir.path(), "secret.php", &encrypt_content(SECRET).unwrap());
+
+        let other_key = MockBackend {
+            decryptor: Decryptor::builder()
+                .key(Key::new(b"another key".as_slice()).unwrap())
+                .build(),
+            ..MockBackend::default()
+        };
+        let (message, code) = refused(process(&other_key, Some(&encrypted)));
+        assert!(message.starts_with(&encrypted));
+        assert_eq!(code, Error::UnknownKey.code());
+
+        let misconfigured = MockBackend {
+            decryptor: Err(Error::Config("bad public key".to_string())),
+            ..MockBackend::default()
+        };
+        assert_eq!(
+            refused(process(&misconfigured, Some(&encrypted))).1,
+            Error::Config(String::new()).code()
+        );
+
+        // 初始化错误保留原来的类型和编号，不会都变成配置错误
+        let unreadable = MockBackend {
+            decryptor: Err(Error::Io(std::io::ErrorKind::PermissionDenied.into())),
+            ..MockBackend::default()
+        };
+        assert_eq!(
+            refused(process(&unreadable, Some(&encrypted))).1,
+            Error::Io(std::io::ErrorKind::Other.into()).code()
+        );
+
+        let source = b"<?php\n".repeat(1000);
+        let mut chunked = Encryptor::builder()
+            .algorithm(Algorithm::ChaCha20)
+            .chunked(1024)
+            .build()
+            .unwrap()
+            .encrypt(&source)
+            .unwrap();
+        let damaged = write(dir.path(), "damaged.php", &{
+            let last = chunked.len() - 1;
+            chunked[last] ^= 1;
+            chunked
+        });
+        let (message, code) = refused(process(&MockBackend::default(), Some(&damaged)));
+        assert!(message.contains("chunk 5"));
+        assert_eq!(code, Error::ChunkAuthenticationFailed(0).code());
+
+        // 第一块之后的块都完好，也不能交出任何明文
+        let mut chunked = std::fs::read(&damaged).unwrap();
+        let last = chunked.len() - 1;
+        chunked[last] ^= 1;
+        let first_tag = chunked.len() - source.len() - 16 * 6 + 1024;
+        chunked[first_tag] ^= 1;
+        let damaged = write(dir.path(), "damaged.php", &chunked);
+        let (message, _) = refused(process(&MockBackend::default(), Some(&damaged)));
+        assert!(message.contains("chunk 0"));
+    }
+
+    #[test]
+    fn test_cache_hits_and_invalidation() {
+        let dir = tempfile::tempdir().unwrap();
+        let encrypted = write(dir.path(), "secret.php", &encrypt_content(SECRET).unwrap());
+        let backend = MockBackend {
+            cache: Some(SourceCache::new(4)),
+            ..MockBackend::default()
+        };
+
+        assert_eq!(compiled_source(process(&backend, Some(&encrypted))), SECRET);
+        assert_eq!(compiled_source(process(&backend, Some(&encrypted))), SECRET);
+        let stats = backend.cache.as_ref().unwrap().stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+
+        // 文件内容和大小变化后重新解密
+        let updated = b"<?php echo 'updated secret';";
+        write(dir.path(), "secret.php", &encrypt_content(updated).unwrap());
+        assert_eq!(
+            compiled_source(process(&backend, Some(&encrypted))),
+            updated
+        );
+        assert_eq!(backend.cache.as_ref().unwrap().stats().misses, 2);
+
+        // 解密失败的查找同样计为未命中，且不进入缓存
+        let foreign = Encryptor::builder()
+            .key(Key::new(b"another key".as_slice()).unwrap())
+            .build()
+            .unwrap()
+            .encrypt(SECRET)
+            .unwrap();
+        write(dir.path(), "secret.php", &foreign);
+        refused(process(&backend, Some(&encrypted)));
+        let stats = backend.cache.as_ref().unwrap().stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 3, 0));
+    }
+}
@@ -0,0 +1,63 @@
+use std::cell::Cell;
+
+use phper::sys;
+
+use php_guard_core::config::DEBUGGER_POLICY;
+use php_guard_core::debugger::{self, DEBUG_FEATURE, DebuggerPolicy, Verdict};
+
+use crate::license;
+use crate::sapi;
+use crate::state::RequestCache;
+
+thread_local! {
+    static WARNED: Cell<bool> = const { Cell::new(false) };
+    static REQUEST_VERDICT: RequestCache<Verdict> = const { RequestCache::new() };
+}
+
+fn module_loaded(name: &str) -> bool {
+    unsafe {
+        !sys::zend_hash_str_find(
+            &raw mut sys::module_registry,
+            name.as_ptr().cast(),
+            name.len(),
+        )
+        .is_null()
+    }
+}
+
+/// Xdebug 作为 zend_extension 在所有扩展的 MINIT 之后才注册模块，因此在请求开始时检测
+fn evaluate() -> Verdict {
+    let detected = debugger::detect(module_loaded, sapi::name().as_deref());
+    let debug_licensed = license::current().is_some_and(|l| l.has_feature(DEBUG_FEATURE));
+    debugger::evaluate(DEBUGGER_POLICY, &detected, debug_licensed)
+}
+
+/// 在 RINIT 中调用，本请求内的每次 include (包括 OPcache 命中) 共用检测结果
+pub fn request_startup() {
+    if enabled() {
+        REQUEST_VERDICT.with(|verdict| verdict.set(evaluate()));
+    }
+}
+
+pub fn enabled() -> bool {
+    DEBUGGER_POLICY != DebuggerPolicy::Allow
+}
+
+/// 在守卫钩子和编译钩子中调用。策略为 `warn` 时每个请求返回一次警告。
+pub fn check() -> Result<Option<String>, String> {
+    if !enabled() {
+        return Ok(None);
+    }
+
+    match REQUEST_VERDICT.with(|verdict| verdict.get_or_init(evaluate)) {
+        Verdict::Allow => Ok(None),
+        Verdict::Warn(_) if WARNED.replace(true) => Ok(None),
+        Verdict::Warn(message) => Ok(Some(message)),
+        Verdict::Refuse(message) => Err(message),
+    }
+}
+
+pub fn request_shutdown() {
+    WARNED.set(false);
+    REQUEST_VERDICT.with(RequestCache::clear);
+}
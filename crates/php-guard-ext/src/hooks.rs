@@ -1,30 +1,99 @@
-use std::ffi::CStr;
-use std::os::raw::c_int;
-use std::os::unix::io::AsRawFd;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
 use std::ptr;
+use std::sync::{Arc, Once, OnceLock};
 
 use phper::sys::{
-    self, zend_compile_file, zend_file_handle, zend_stream_type_ZEND_HANDLE_FILENAME,
-    zend_stream_type_ZEND_HANDLE_FP, zend_stream_type_ZEND_HANDLE_STREAM,
+    self, zend_file_handle, zend_stream_type_ZEND_HANDLE_FILENAME, zend_stream_type_ZEND_HANDLE_FP,
+    zend_stream_type_ZEND_HANDLE_STREAM,
 };
 
-use php_guard_core::config::HEADER;
-use php_guard_core::crypto::decode;
+use php_guard_core::signing;
+use php_guard_core::{Decryptor, Error, SecretBytes};
 
-static mut ORIGINAL_COMPILE_FILE: Option<
-    unsafe extern "C" fn(*mut zend_file_handle, c_int) -> *mut sys::_zend_op_array,
-> = None;
+use crate::cache::{self, SourceCache};
+use crate::compile::{self, CompileBackend, Outcome};
+use crate::debugger;
+use crate::license;
+use crate::opcache;
+use crate::state::{self, CompileFileFn, Event};
 
-pub unsafe fn init_hooks() {
+fn is_ours(func: Option<CompileFileFn>) -> bool {
+    func.is_some_and(|f| std::ptr::fn_addr_eq(f, php_guard_compile_file as CompileFileFn))
+}
+
+fn is_guard(func: Option<CompileFileFn>) -> bool {
+    func.is_some_and(|f| std::ptr::fn_addr_eq(f, php_guard_guard_compile_file as CompileFileFn))
+}
+
+/// 在 MINIT 中调用：记录当前的 `zend_compile_file` 作为链中的下一环，再把自己放到链首。
+///
+/// 在我们之前加载的扩展 (ionCube 类 loader) 会被我们包装；之后加载的 opcache、Xdebug
+/// 会包装我们，因此它们缓存/调试的是解密后的源码。
+pub unsafe fn install() {
     unsafe {
-        ORIGINAL_COMPILE_FILE = Some(zend_compile_file.unwrap());
+        let current = sys::zend_compile_file;
+        if is_ours(current) {
+            // 重复 MINIT 时不能把自己记成原始函数，否则会无限递归
+            return;
+        }
+        state::replace_original_compile_file(current);
+        sys::zend_compile_file = Some(php_guard_compile_file);
+        state::set_hook_active(true);
     }
 }
 
-pub unsafe fn restore_hooks() {
+static GUARD_INSTALLED: Once = Once::new();
+
+/// 在 RINIT 中调用：把守卫钩子放到链首，每个进程只安装一次。
+///
+/// OPcache 在 MINIT 之后包装了 [`php_guard_compile_file`]，缓存命中时不会再调用它。
+/// 第一次 RINIT 时所有扩展都已启动，守卫钩子因此位于 OPcache 之前，
+/// 每次 include 受保护文件都会经过授权和调试器检查。
+pub unsafe fn install_guard() {
+    GUARD_INSTALLED.call_once(|| unsafe {
+        let current = sys::zend_compile_file;
+        if is_guard(current) {
+            return;
+        }
+        state::replace_guard_next_compile_file(current);
+        sys::zend_compile_file = Some(php_guard_guard_compile_file);
+    });
+}
+
+/// 在 MSHUTDOWN 中调用。
+///
+/// 只有仍处于链首时才恢复原始函数；如果之后有扩展包装了我们，它持有的是我们的指针，
+/// 此时保留原始函数并切换为直通模式，避免把链截断。PHP 在 MSHUTDOWN 之后会 dlclose
+/// 扩展，这种情况下把共享库标记为常驻，保证那个指针在进程退出前一直有效。
+pub unsafe fn uninstall() {
     unsafe {
-        if let Some(original) = ORIGINAL_COMPILE_FILE {
-            sys::zend_compile_file = Some(original);
+        state::set_hook_active(false);
+        if is_guard(sys::zend_compile_file)
+            && let Some(next) = state::replace_guard_next_compile_file(None)
+        {
+            sys::zend_compile_file = Some(next);
+        }
+        if is_ours(sys::zend_compile_file) {
+            if let Some(original) = state::replace_original_compile_file(None) {
+                sys::zend_compile_file = Some(original);
+            }
+        } else {
+            keep_resident();
+        }
+    }
+}
+
+/// 用 `RTLD_NODELETE` 再次打开本扩展的共享库，之后的 dlclose 不会把它卸载。
+///
+/// 只增加引用计数，不会重新执行初始化；失败时 (如静态编译进 PHP) 没有需要保护的卸载。
+unsafe fn keep_resident() {
+    unsafe {
+        let mut info: libc::Dl_info = std::mem::zeroed();
+        let address = php_guard_compile_file as *const c_void;
+        if libc::dladdr(address, &mut info) != 0 && !info.dli_fname.is_null() {
+            let flags = libc::RTLD_NOW | libc::RTLD_NOLOAD | libc::RTLD_NODELETE;
+            libc::dlopen(info.dli_fname, flags);
         }
     }
 }
@@ -34,13 +103,25 @@ unsafe fn call_original(
     type_: c_int,
 ) -> *mut sys::_zend_op_array {
     unsafe {
-        match ORIGINAL_COMPILE_FILE {
+        match state::original_compile_file() {
             Some(original) => original(file_handle, type_),
             None => ptr::null_mut(),
         }
     }
 }
 
+unsafe fn call_guard_next(
+    file_handle: *mut zend_file_handle,
+    type_: c_int,
+) -> *mut sys::_zend_op_array {
+    unsafe {
+        match state::guard_next_compile_file() {
+            Some(next) => next(file_handle, type_),
+            None => ptr::null_mut(),
+        }
+    }
+}
+
 unsafe fn get_filename_str(handle: &zend_file_handle) -> Option<String> {
     unsafe {
         if handle.filename.is_null() {
@@ -53,107 +134,194 @@ unsafe fn get_filename_str(handle: &zend_file_handle) -> Option<String> {
     }
 }
 
-fn should_decrypt(filename: &str) -> bool {
-    if filename == "-" {
-        return false;
-    }
-    if filename.starts_with("phar:") {
-        return false;
+static DECRYPTOR: OnceLock<php_guard_core::Result<Decryptor>> = OnceLock::new();
+
+/// 构建配置的密钥和头部；配置了签名公钥时要求文件带有效签名
+fn build_decryptor(lock_memory: bool) -> php_guard_core::Result<Decryptor> {
+    let mut builder = Decryptor::builder().lock_memory(lock_memory);
+    if let Some(key) = signing::build_verifying_key()? {
+        builder = builder.verifying_key(key);
     }
-    true
+    builder.build()
+}
+
+/// 在 MINIT 中调用，读取 ini 后创建解密器
+pub fn init(lock_memory: bool) {
+    let _ = DECRYPTOR.set(build_decryptor(lock_memory));
 }
 
-fn try_decrypt(filename: &str) -> Option<Vec<u8>> {
-    let content = std::fs::read(filename).ok()?;
+/// 初始化失败时每次都交出原始错误，PHP 异常的 code 与 [`Error::code`] 一致
+fn decryptor() -> php_guard_core::Result<&'static Decryptor> {
+    DECRYPTOR
+        .get_or_init(|| build_decryptor(false))
+        .as_ref()
+        .map_err(Error::clone)
+}
 
-    if content.len() < HEADER.len() {
-        return None;
-    }
+/// 由 PHP 运行环境提供的 [`CompileBackend`]
+struct ZendBackend;
 
-    if &content[..HEADER.len()] != HEADER {
-        return None;
+impl CompileBackend for ZendBackend {
+    fn hook_active(&self) -> bool {
+        state::hook_active()
     }
 
-    let mut decrypted = content[HEADER.len()..].to_vec();
-    decode(&mut decrypted);
+    fn file_cache_active(&self) -> bool {
+        opcache::file_cache_active()
+    }
 
-    Some(decrypted)
-}
+    fn checks_enabled(&self) -> bool {
+        license::enabled() || debugger::enabled()
+    }
 
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn php_guard_compile_file(
-    file_handle: *mut zend_file_handle,
-    type_: c_int,
-) -> *mut sys::_zend_op_array {
-    if file_handle.is_null() {
-        return unsafe { call_original(file_handle, type_) };
+    fn check_license(&self) -> Result<Option<String>, String> {
+        license::check()
     }
 
-    let handle = unsafe { &mut *file_handle };
+    fn check_debugger(&self) -> Result<Option<String>, String> {
+        debugger::check()
+    }
 
-    let filename = match unsafe { get_filename_str(handle) } {
-        Some(s) => s,
-        None => return unsafe { call_original(file_handle, type_) },
-    };
+    fn warn(&self, message: &str) {
+        warn(message);
+    }
 
-    if !should_decrypt(&filename) {
-        return unsafe { call_original(file_handle, type_) };
+    fn decryptor(&self) -> php_guard_core::Result<&Decryptor> {
+        decryptor()
     }
 
-    let decrypted = match try_decrypt(&filename) {
-        Some(d) => d,
-        None => return unsafe { call_original(file_handle, type_) },
-    };
+    fn cache(&self) -> Option<&SourceCache> {
+        cache::global()
+    }
+}
 
-    let mut temp_file = match tempfile::tempfile() {
-        Ok(f) => f,
-        Err(_) => return unsafe { call_original(file_handle, type_) },
-    };
+/// 抛出异常并中止编译，与 PHP 处理 ParseError 的方式一致：返回 NULL 且 EG(exception) 已设置。
+///
+/// 解密失败时 `code` 为 [`Error::code`]，PHP 侧可用 `$e->getCode()` 区分原因；其他情况为 0。
+unsafe fn refuse(message: &str, code: i32) -> *mut sys::_zend_op_array {
+    let message = CString::new(format!("php-guard: {}", message)).unwrap_or_default();
+    unsafe {
+        sys::zend_throw_exception(ptr::null_mut(), message.as_ptr(), code.into());
+    }
+    ptr::null_mut()
+}
 
-    use std::io::{Seek, SeekFrom, Write};
-    if temp_file.write_all(&decrypted).is_err() {
-        return unsafe { call_original(file_handle, type_) };
+fn warn(message: &str) {
+    let message = CString::new(format!("php-guard: {}", message)).unwrap_or_default();
+    unsafe {
+        sys::php_error_docref(
+            ptr::null(),
+            sys::E_WARNING as c_int,
+            c"%s".as_ptr(),
+            message.as_ptr(),
+        );
     }
-    if temp_file.seek(SeekFrom::Start(0)).is_err() {
-        return unsafe { call_original(file_handle, type_) };
+}
+
+/// 编译期间交给 Zend 读取的明文。Zend 在销毁文件句柄时调用 closer 释放，
+/// 最后一个引用 (缓存中的条目也是引用) 释放时明文清零。
+struct MemorySource {
+    source: Arc<SecretBytes>,
+    position: usize,
+}
+
+/// PHP 7 的 reader 返回 `size_t`，PHP 8 返回 `ssize_t`，返回类型由 bindgen 生成的字段类型推导
+unsafe extern "C" fn read_source<R: TryFrom<usize> + Default>(
+    handle: *mut c_void,
+    buf: *mut c_char,
+    len: usize,
+) -> R {
+    let memory = unsafe { &mut *handle.cast::<MemorySource>() };
+    let remaining = &memory.source[memory.position..];
+    let n = remaining.len().min(len);
+    unsafe {
+        ptr::copy_nonoverlapping(remaining.as_ptr(), buf.cast(), n);
     }
+    memory.position += n;
+    R::try_from(n).unwrap_or_default()
+}
 
+unsafe extern "C" fn source_size(handle: *mut c_void) -> usize {
+    let memory = unsafe { &*handle.cast::<MemorySource>() };
+    memory.source.len()
+}
+
+unsafe extern "C" fn close_source(handle: *mut c_void) {
+    drop(unsafe { Box::from_raw(handle.cast::<MemorySource>()) });
+}
+
+/// 关闭文件句柄上已打开的文件，改为从内存中的明文读取
+unsafe fn attach_source(handle: &mut zend_file_handle, source: Arc<SecretBytes>) {
     match handle.type_ {
         zend_stream_type_ZEND_HANDLE_FP => unsafe {
             if !handle.handle.fp.is_null() {
                 libc::fclose(handle.handle.fp.cast());
-                handle.handle.fp = ptr::null_mut();
             }
         },
         zend_stream_type_ZEND_HANDLE_STREAM => unsafe {
-            if !handle.handle.stream.handle.is_null() {
-                if let Some(closer) = handle.handle.stream.closer {
-                    closer(handle.handle.stream.handle);
-                }
+            if !handle.handle.stream.handle.is_null()
+                && let Some(closer) = handle.handle.stream.closer
+            {
+                closer(handle.handle.stream.handle);
             }
-            handle.handle.stream.handle = ptr::null_mut();
         },
         zend_stream_type_ZEND_HANDLE_FILENAME => {}
         _ => {}
     }
 
-    let fd = temp_file.as_raw_fd();
-    let new_fp = unsafe { libc::fdopen(fd, b"r\0".as_ptr().cast()) };
-    if new_fp.is_null() {
-        return unsafe { call_original(file_handle, type_) };
+    let memory = Box::new(MemorySource {
+        source,
+        position: 0,
+    });
+    unsafe {
+        handle.handle.stream = std::mem::zeroed();
+        handle.handle.stream.handle = Box::into_raw(memory).cast();
     }
+    handle.handle.stream.reader = Some(read_source);
+    handle.handle.stream.fsizer = Some(source_size);
+    handle.handle.stream.closer = Some(close_source);
+    handle.type_ = zend_stream_type_ZEND_HANDLE_STREAM;
+}
 
-    unsafe {
-        handle.handle.fp = new_fp.cast();
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn php_guard_compile_file(
+    file_handle: *mut zend_file_handle,
+    type_: c_int,
+) -> *mut sys::_zend_op_array {
+    if file_handle.is_null() {
+        return unsafe { call_original(file_handle, type_) };
     }
-    handle.type_ = zend_stream_type_ZEND_HANDLE_FP;
-    std::mem::forget(temp_file);
+
+    let handle = unsafe { &mut *file_handle };
+    let filename = unsafe { get_filename_str(handle) };
+
+    let source = match compile::run(&ZendBackend, filename.as_deref()) {
+        Outcome::Decrypted(source) => source,
+        Outcome::Original | Outcome::Passthrough => {
+            return unsafe { call_original(file_handle, type_) };
+        }
+        Outcome::Refuse { message, code } => return unsafe { refuse(&message, code) },
+    };
+
+    unsafe { attach_source(handle, source) };
 
     unsafe { call_original(file_handle, type_) }
 }
 
-pub unsafe fn register_hooks() {
-    unsafe {
-        sys::zend_compile_file = Some(php_guard_compile_file);
+/// 守卫钩子：检查不通过时抛出异常，否则交给链中的下一环 (通常是 OPcache)
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn php_guard_guard_compile_file(
+    file_handle: *mut zend_file_handle,
+    type_: c_int,
+) -> *mut sys::_zend_op_array {
+    if !file_handle.is_null() {
+        let filename = unsafe { get_filename_str(&*file_handle) };
+        if let Outcome::Refuse { message, code } = compile::guard(&ZendBackend, filename.as_deref())
+        {
+            state::record(Event::Failed);
+            return unsafe { refuse(&message, code) };
+        }
     }
+
+    unsafe { call_guard_next(file_handle, type_) }
 }
@@ -1,4 +1,22 @@
+// 单元测试不包含 Zend 入口 (MINIT、compile hook) 和读取 PHP 运行时状态的模块，
+// 测试二进制因此不引用任何 PHP 符号，不需要 PHP 进程也能链接和运行
+#![cfg_attr(test, allow(dead_code))]
+
+mod cache;
+mod compile;
+#[cfg(not(test))]
+mod debugger;
+#[cfg(not(test))]
 mod hooks;
+#[cfg(not(test))]
+mod license;
+#[cfg(not(test))]
+mod opcache;
+#[cfg(not(test))]
 mod php_extension;
+#[cfg(not(test))]
+mod sapi;
+mod state;
 
+#[cfg(not(test))]
 pub use php_extension::register_module;
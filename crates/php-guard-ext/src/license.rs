@@ -0,0 +1,120 @@
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::sync::OnceLock;
+
+use phper::ini::ini_get;
+
+use php_guard_core::config::REQUIRE_LICENSE;
+use php_guard_core::{Date, License, LicenseStatus};
+
+use crate::sapi;
+use crate::state::RequestCache;
+
+static LICENSE: OnceLock<Result<Option<License>, String>> = OnceLock::new();
+
+thread_local! {
+    static GRACE_WARNED: Cell<bool> = const { Cell::new(false) };
+    // 域名随请求变化，授权结果在 RINIT 时计算，同一请求内的 include 共用
+    static REQUEST_VERDICT: RequestCache<Result<LicenseStatus, String>> =
+        const { RequestCache::new() };
+}
+
+fn ini_string(name: &str) -> Option<String> {
+    ini_get::<Option<&CStr>>(name)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+fn load() -> Result<Option<License>, String> {
+    let path = ini_string("php_guard.license_file");
+
+    let Some(path) = path else {
+        return if REQUIRE_LICENSE {
+            Err("no license configured, set php_guard.license_file".to_string())
+        } else {
+            Ok(None)
+        };
+    };
+
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| format!("cannot read license file {}: {}", path, e))?;
+    let license =
+        License::parse(&text).map_err(|e| format!("invalid license file {}: {}", path, e))?;
+
+    // 主机指纹在加载时计算一次，绑定的来源 (machine-id、MAC 等) 在进程生命周期内不变
+    let host_id = ini_string("php_guard.host_id");
+    license
+        .check_current_host(host_id.as_deref())
+        .map_err(|e| format!("license {}: {}", path, e))?;
+    license
+        .check_sapi(&sapi::name().unwrap_or_default())
+        .map_err(|e| format!("license {}: {}", path, e))?;
+
+    Ok(Some(license))
+}
+
+/// 在 MINIT 中调用，提前加载授权并计算主机指纹
+pub fn init() {
+    LICENSE.get_or_init(load);
+}
+
+pub fn current() -> Option<&'static License> {
+    LICENSE
+        .get_or_init(load)
+        .as_ref()
+        .ok()
+        .and_then(Option::as_ref)
+}
+
+fn evaluate(license: &License) -> Result<LicenseStatus, String> {
+    license
+        .check_domain(sapi::request_host().as_deref())
+        .map_err(|e| e.to_string())?;
+    license.check(Date::today()).map_err(|e| e.to_string())
+}
+
+/// 在 RINIT 中调用，按本请求的域名和当天日期计算授权结果
+pub fn request_startup() {
+    if let Ok(Some(license)) = LICENSE.get_or_init(load) {
+        REQUEST_VERDICT.with(|verdict| verdict.set(evaluate(license)));
+    }
+}
+
+/// 配置了授权文件，或构建要求授权
+pub fn enabled() -> bool {
+    !matches!(LICENSE.get_or_init(load), Ok(None))
+}
+
+/// 在守卫钩子和编译钩子中调用。授权文件只在第一次使用时加载，域名和到期时间每个请求
+/// 检查一次，结果对本请求内的每次 include (包括 OPcache 命中) 生效，常驻进程在授权到期后
+/// 也会停止运行受保护代码。
+///
+/// 宽限期内返回一条警告，每个请求只返回一次。
+pub fn check() -> Result<Option<String>, String> {
+    let license = match LICENSE.get_or_init(load) {
+        Ok(Some(license)) => license,
+        Ok(None) => return Ok(None),
+        Err(message) => return Err(message.clone()),
+    };
+
+    match REQUEST_VERDICT.with(|verdict| verdict.get_or_init(|| evaluate(license)))? {
+        LicenseStatus::Grace { days_left } => {
+            if GRACE_WARNED.replace(true) {
+                return Ok(None);
+            }
+            Ok(Some(format!(
+                "license for {} expired on {}, protected code stops running in {} day(s)",
+                license.customer,
+                license.expires.map(|d| d.to_string()).unwrap_or_default(),
+                days_left
+            )))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub fn request_shutdown() {
+    GRACE_WARNED.set(false);
+    REQUEST_VERDICT.with(RequestCache::clear);
+}
@@ -0,0 +1,29 @@
+use std::ffi::CStr;
+use std::sync::OnceLock;
+
+use phper::ini::ini_get;
+
+use crate::sapi;
+
+static FILE_CACHE_ACTIVE: OnceLock<bool> = OnceLock::new();
+
+/// `opcache.file_cache` 会把编译后的 op array 明文写入磁盘，受保护文件不能进入其中。
+///
+/// opcache 作为 zend_extension 在我们的 MINIT 之后才启动，所以在第一次编译时检测；
+/// 相关配置都是 `PHP_INI_SYSTEM`，检测结果在进程内不会变化。
+pub fn file_cache_active() -> bool {
+    *FILE_CACHE_ACTIVE.get_or_init(detect_file_cache)
+}
+
+fn detect_file_cache() -> bool {
+    let file_cache = ini_get::<Option<&CStr>>("opcache.file_cache");
+    if file_cache.is_none_or(|dir| dir.is_empty()) {
+        return false;
+    }
+
+    if sapi::is_cli() {
+        ini_get::<bool>("opcache.enable_cli")
+    } else {
+        ini_get::<bool>("opcache.enable")
+    }
+}
@@ -1,6 +1,18 @@
+use crate::cache;
+use crate::debugger;
 use crate::hooks;
-
-use phper::{functions::Argument, modules::Module, php_get_module, strings::ZString, values::ZVal};
+use crate::license;
+use crate::state::{self, Stats};
+
+use phper::{
+    arrays::ZArray,
+    functions::Argument,
+    ini::{Policy, ini_get},
+    modules::Module,
+    php_get_module,
+    strings::ZString,
+    values::ZVal,
+};
 
 use php_guard_core::{crypto, file_handler};
 
@@ -16,7 +28,7 @@ fn php_guard_encode(arguments: &mut [ZVal]) -> phper::Result<Option<ZString>> {
         return Ok(None);
     }
 
-    let encrypted = file_handler::encrypt_content(content_bytes);
+    let encrypted = file_handler::encrypt_content(content_bytes).map_err(phper::Error::boxed)?;
     Ok(Some(ZString::new(&encrypted)))
 }
 
@@ -26,10 +38,50 @@ fn php_guard_is_encrypted(arguments: &mut [ZVal]) -> phper::Result<bool> {
     Ok(crypto::is_encrypted(content_bytes))
 }
 
+fn stats_to_array(stats: Stats) -> ZArray {
+    let mut arr = ZArray::new();
+    arr.insert("decrypted", ZVal::from(stats.decrypted as i64));
+    arr.insert("passthrough", ZVal::from(stats.passthrough as i64));
+    arr.insert("failed", ZVal::from(stats.failed as i64));
+    arr
+}
+
+fn php_guard_stats(_arguments: &mut [ZVal]) -> phper::Result<ZArray> {
+    let mut arr = ZArray::new();
+    arr.insert(
+        "request",
+        ZVal::from(stats_to_array(state::request_stats())),
+    );
+    arr.insert("total", ZVal::from(stats_to_array(state::total_stats())));
+
+    if let Some(cache) = cache::global() {
+        let stats = cache.stats();
+        let mut cache_arr = ZArray::new();
+        cache_arr.insert("hits", ZVal::from(stats.hits as i64));
+        cache_arr.insert("misses", ZVal::from(stats.misses as i64));
+        cache_arr.insert("entries", ZVal::from(stats.entries as i64));
+        arr.insert("cache", ZVal::from(cache_arr));
+    }
+
+    Ok(arr)
+}
+
 fn php_guard_version(_arguments: &mut [ZVal]) -> phper::Result<&'static str> {
     Ok(MODULE_VERSION)
 }
 
+fn request_init() {
+    unsafe { hooks::install_guard() }
+    license::request_startup();
+    debugger::request_startup();
+}
+
+fn request_shutdown() {
+    state::request_shutdown();
+    license::request_shutdown();
+    debugger::request_shutdown();
+}
+
 #[php_get_module]
 pub fn get_module() -> Module {
     register_module()
@@ -47,19 +99,27 @@ pub fn register_module() -> Module {
         .argument(Argument::new("content"));
 
     module.add_function("php_guard_version", php_guard_version);
-
-    unsafe {
-        hooks::init_hooks();
-        hooks::register_hooks();
-    }
+    module.add_function("php_guard_stats", php_guard_stats);
+
+    // 常驻进程 (Swoole/RoadRunner worker, 关闭 opcache 的 CLI) 的解密源码缓存条目数，0 为关闭
+    module.add_ini("php_guard.cache_size", 0_i64, Policy::System);
+    // 授权文件路径；构建时设置了 PHP_GUARD_REQUIRE_LICENSE 时必须提供
+    module.add_ini("php_guard.license_file", String::new(), Policy::System);
+    // 授权绑定 custom 指纹来源时使用的客户自定义标识
+    module.add_ini("php_guard.host_id", String::new(), Policy::System);
+    // 用 mlock 锁定解密后的源码，防止被换出到交换分区；受 RLIMIT_MEMLOCK 限制，锁定失败时照常运行
+    module.add_ini("php_guard.mlock", false, Policy::System);
+
+    module.on_module_init(|| {
+        let cache_size = ini_get::<i64>("php_guard.cache_size");
+        cache::init(cache_size.max(0) as usize);
+        hooks::init(ini_get::<bool>("php_guard.mlock"));
+        license::init();
+        unsafe { hooks::install() }
+    });
+    module.on_module_shutdown(|| unsafe { hooks::uninstall() });
+    module.on_request_init(request_init);
+    module.on_request_shutdown(request_shutdown);
 
     module
 }
-
-#[unsafe(no_mangle)]
-pub extern "C" fn php_guard_mshutdown(_type: i32, _module_number: i32) -> i32 {
-    unsafe {
-        hooks::restore_hooks();
-    }
-    0
-}
@@ -0,0 +1,41 @@
+use std::ffi::{CStr, CString};
+
+use phper::sys;
+
+pub fn name() -> Option<String> {
+    unsafe {
+        let name = sys::sapi_module.name;
+        if name.is_null() {
+            return None;
+        }
+        CStr::from_ptr(name).to_str().ok().map(str::to_string)
+    }
+}
+
+pub fn is_cli() -> bool {
+    name().is_some_and(|name| name == "cli")
+}
+
+/// 读取当前请求的服务器变量 (FPM 的 FastCGI 参数、Apache 的 subprocess_env 等)。
+///
+/// 直接调用 SAPI 的 getenv 回调，返回的字符串归 SAPI 所有，不需要释放；
+/// CLI 等没有请求环境的 SAPI 返回 `None`。
+pub fn server_var(name: &str) -> Option<String> {
+    let name = CString::new(name).ok()?;
+    unsafe {
+        let getenv = sys::sapi_module.getenv?;
+        let value = getenv(name.as_ptr(), name.as_bytes().len());
+        if value.is_null() {
+            return None;
+        }
+        CStr::from_ptr(value)
+            .to_str()
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    }
+}
+
+pub fn request_host() -> Option<String> {
+    server_var("HTTP_HOST").or_else(|| server_var("SERVER_NAME"))
+}
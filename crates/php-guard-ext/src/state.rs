@@ -0,0 +1,286 @@
+use std::cell::{Cell, RefCell};
+use std::os::raw::c_int;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use phper::sys::{self, zend_file_handle};
+
+pub type CompileFileFn =
+    unsafe extern "C" fn(*mut zend_file_handle, c_int) -> *mut sys::_zend_op_array;
+
+// ZTS 下多个线程会同时进入 compile hook，原始函数指针只在 MINIT/MSHUTDOWN 时写入
+static ORIGINAL_COMPILE_FILE: RwLock<Option<CompileFileFn>> = RwLock::new(None);
+
+pub fn original_compile_file() -> Option<CompileFileFn> {
+    *ORIGINAL_COMPILE_FILE
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn replace_original_compile_file(func: Option<CompileFileFn>) -> Option<CompileFileFn> {
+    let mut guard = ORIGINAL_COMPILE_FILE
+        .write()
+        .unwrap_or_else(|e| e.into_inner());
+    std::mem::replace(&mut *guard, func)
+}
+
+// 守卫钩子在第一次 RINIT 时安装到 OPcache 之前，这里是它在链中的下一环
+static GUARD_NEXT_COMPILE_FILE: RwLock<Option<CompileFileFn>> = RwLock::new(None);
+
+pub fn guard_next_compile_file() -> Option<CompileFileFn> {
+    *GUARD_NEXT_COMPILE_FILE
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn replace_guard_next_compile_file(func: Option<CompileFileFn>) -> Option<CompileFileFn> {
+    let mut guard = GUARD_NEXT_COMPILE_FILE
+        .write()
+        .unwrap_or_else(|e| e.into_inner());
+    std::mem::replace(&mut *guard, func)
+}
+
+static HOOK_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub fn hook_active() -> bool {
+    HOOK_ACTIVE.load(Ordering::Acquire)
+}
+
+pub fn set_hook_active(active: bool) {
+    HOOK_ACTIVE.store(active, Ordering::Release);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Decrypted,
+    Passthrough,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub decrypted: u64,
+    pub passthrough: u64,
+    pub failed: u64,
+}
+
+impl Stats {
+    fn bump(&mut self, event: Event) {
+        match event {
+            Event::Decrypted => self.decrypted += 1,
+            Event::Passthrough => self.passthrough += 1,
+            Event::Failed => self.failed += 1,
+        }
+    }
+}
+
+static TOTAL_DECRYPTED: AtomicU64 = AtomicU64::new(0);
+static TOTAL_PASSTHROUGH: AtomicU64 = AtomicU64::new(0);
+static TOTAL_FAILED: AtomicU64 = AtomicU64::new(0);
+
+// NTS 只有一个线程；ZTS 下每个请求固定在一个线程上执行，因此线程局部即请求局部
+thread_local! {
+    static REQUEST_STATS: Cell<Stats> = const {
+        Cell::new(Stats {
+            decrypted: 0,
+            passthrough: 0,
+            failed: 0,
+        })
+    };
+}
+
+pub fn record(event: Event) {
+    REQUEST_STATS.with(|stats| {
+        let mut current = stats.get();
+        current.bump(event);
+        stats.set(current);
+    });
+
+    let total = match event {
+        Event::Decrypted => &TOTAL_DECRYPTED,
+        Event::Passthrough => &TOTAL_PASSTHROUGH,
+        Event::Failed => &TOTAL_FAILED,
+    };
+    total.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn request_stats() -> Stats {
+    REQUEST_STATS.with(Cell::get)
+}
+
+pub fn total_stats() -> Stats {
+    Stats {
+        decrypted: TOTAL_DECRYPTED.load(Ordering::Relaxed),
+        passthrough: TOTAL_PASSTHROUGH.load(Ordering::Relaxed),
+        failed: TOTAL_FAILED.load(Ordering::Relaxed),
+    }
+}
+
+pub fn reset_request_stats() {
+    REQUEST_STATS.with(|stats| stats.set(Stats::default()));
+}
+
+/// 每个请求只计算一次的结果，放在 `thread_local!` 中使用。
+///
+/// RINIT 时用 [`RequestCache::set`] 写入；预加载等没有经过 RINIT 的编译在第一次读取时计算。
+/// RSHUTDOWN 时清除。
+pub struct RequestCache<T>(RefCell<Option<T>>);
+
+impl<T: Clone> RequestCache<T> {
+    pub const fn new() -> Self {
+        RequestCache(RefCell::new(None))
+    }
+
+    pub fn get_or_init(&self, init: impl FnOnce() -> T) -> T {
+        self.0.borrow_mut().get_or_insert_with(init).clone()
+    }
+
+    pub fn set(&self, value: T) {
+        self.0.replace(Some(value));
+    }
+
+    pub fn clear(&self) {
+        self.0.replace(None);
+    }
+}
+
+/// RSHUTDOWN：清理请求局部状态，避免 ZTS 线程池中的下一个请求看到旧数据
+pub fn request_shutdown() {
+    reset_request_stats();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::{self, Outcome, mock::MockBackend};
+
+    use php_guard_core::encrypt_content;
+    use std::sync::{Arc, Barrier, Mutex};
+    use std::thread;
+
+    // 累计计数是进程级的，测试之间需要串行
+    static TOTALS_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_concurrent_includes() {
+        let _guard = TOTALS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let plain = b"<?php echo 'plain';".to_vec();
+        let secret = b"<?php echo 'secret';".to_vec();
+
+        let plain_path = dir.path().join("plain.php");
+        let encrypted_path = dir.path().join("encrypted.php");
+        std::fs::write(&plain_path, &plain).unwrap();
+        std::fs::write(&encrypted_path, encrypt_content(&secret).unwrap()).unwrap();
+
+        let before = total_stats();
+        let threads = 8;
+        let includes = 50;
+        let barrier = Arc::new(Barrier::new(threads));
+
+        let handles: Vec<_> = (0..threads)
+            .map(|n| {
+                let barrier = Arc::clone(&barrier);
+                let plain_path = plain_path.clone();
+                let encrypted_path = encrypted_path.clone();
+                let secret = secret.clone();
+                thread::spawn(move || {
+                    let backend = MockBackend::default();
+                    reset_request_stats();
+                    barrier.wait();
+                    for i in 0..includes {
+                        let path = if (i + n) % 2 == 0 {
+                            &encrypted_path
+                        } else {
+                            &plain_path
+                        };
+                        match compile::run(&backend, path.to_str()) {
+                            Outcome::Decrypted(source) => assert_eq!(source.as_slice(), secret),
+                            Outcome::Passthrough => {}
+                            other => panic!("unexpected outcome {:?}", other),
+                        }
+                    }
+                    request_stats()
+                })
+            })
+            .collect();
+
+        let mut decrypted = 0;
+        let mut passthrough = 0;
+        for handle in handles {
+            let stats = handle.join().unwrap();
+            assert_eq!(stats.decrypted + stats.passthrough, includes as u64);
+            assert_eq!(stats.failed, 0);
+            decrypted += stats.decrypted;
+            passthrough += stats.passthrough;
+        }
+
+        let after = total_stats();
+        assert_eq!(after.decrypted - before.decrypted, decrypted);
+        assert_eq!(after.passthrough - before.passthrough, passthrough);
+        assert_eq!(decrypted + passthrough, (threads * includes) as u64);
+    }
+
+    #[test]
+    fn test_request_cache_computes_once_per_request() {
+        thread_local! {
+            static VERDICT: RequestCache<Result<(), String>> = const { RequestCache::new() };
+        }
+        let calls = Cell::new(0);
+        let evaluate = |host: &str| {
+            calls.set(calls.get() + 1);
+            if host == "allowed.example.com" {
+                Ok(())
+            } else {
+                Err(format!("domain {} is not licensed", host))
+            }
+        };
+
+        // RINIT 写入后，同一请求内的 include 不再重新计算
+        VERDICT.with(|v| v.set(evaluate("allowed.example.com")));
+        for _ in 0..3 {
+            assert_eq!(
+                VERDICT.with(|v| v.get_or_init(|| evaluate("other.com"))),
+                Ok(())
+            );
+        }
+        assert_eq!(calls.get(), 1);
+
+        // 下一个请求重新计算
+        VERDICT.with(RequestCache::clear);
+        assert!(
+            VERDICT
+                .with(|v| v.get_or_init(|| evaluate("other.com")))
+                .is_err()
+        );
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn test_request_stats_are_thread_local() {
+        let _guard = TOTALS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        reset_request_stats();
+        record(Event::Failed);
+
+        let other = thread::spawn(|| {
+            record(Event::Decrypted);
+            request_stats()
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(
+            other,
+            Stats {
+                decrypted: 1,
+                passthrough: 0,
+                failed: 0
+            }
+        );
+        assert_eq!(request_stats().failed, 1);
+        assert_eq!(request_stats().decrypted, 0);
+
+        reset_request_stats();
+        assert_eq!(request_stats(), Stats::default());
+    }
+}
@@ -0,0 +1,61 @@
+//! 集成测试共用的 PHP 进程启动逻辑。
+//!
+//! 需要本机可用的 `php` (带 opcache) 和已编译的扩展；缺少任一项时跳过。
+//! 可通过 `PHP_GUARD_PHP` / `PHP_GUARD_EXT_SO` 指定路径。
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+pub fn php_binary() -> Option<PathBuf> {
+    let php = std::env::var_os("PHP_GUARD_PHP")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("php"));
+    let ok = Command::new(&php)
+        .arg("-v")
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false);
+    ok.then_some(php)
+}
+
+pub fn extension_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("PHP_GUARD_EXT_SO") {
+        return Some(PathBuf::from(path));
+    }
+    let target = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../target");
+    ["debug", "release"]
+        .iter()
+        .map(|profile| target.join(profile).join("libphp_guard_ext.so"))
+        .find(|path| path.exists())
+}
+
+pub fn run_php(php: &Path, ext: &Path, extra: &[&str], script: &Path) -> Output {
+    let mut cmd = Command::new(php);
+    cmd.arg("-n")
+        .arg(format!("-dextension={}", ext.display()))
+        .arg("-dzend_extension=opcache")
+        .arg("-dopcache.enable=1")
+        .arg("-dopcache.enable_cli=1")
+        .arg("-dopcache.validate_timestamps=1")
+        .arg("-dopcache.revalidate_freq=0");
+    for arg in extra {
+        cmd.arg(arg);
+    }
+    cmd.arg(script).output().expect("failed to run php")
+}
+
+#[macro_export]
+macro_rules! require_php {
+    () => {
+        match (
+            $crate::common::php_binary(),
+            $crate::common::extension_path(),
+        ) {
+            (Some(php), Some(ext)) => (php, ext),
+            _ => {
+                eprintln!("skipping: php or libphp_guard_ext.so not available");
+                return;
+            }
+        }
+    };
+}
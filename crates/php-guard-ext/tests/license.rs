@@ -0,0 +1,77 @@
+//! 在真实的 PHP 进程中验证授权检查对 OPcache 缓存中的受保护脚本同样生效。
+//!
+//! 扩展只接受构建时 `PHP_GUARD_SIGN_PUBKEY` 对应私钥签发的授权，测试通过
+//! `PHP_GUARD_TEST_SIGN_KEY` 读取该私钥文件 (`keygen` 生成)；未设置时跳过。
+
+mod common;
+
+use common::run_php;
+use php_guard_core::signing::{self, SigningKey};
+use php_guard_core::{Date, License, encrypt_content_signed};
+
+fn signing_key() -> Option<SigningKey> {
+    let path = std::env::var_os("PHP_GUARD_TEST_SIGN_KEY")?;
+    let hex = std::fs::read_to_string(path).expect("failed to read signing key");
+    Some(signing::signing_key_from_hex(hex.trim()).expect("invalid signing key"))
+}
+
+#[test]
+fn test_expired_license_refuses_cached_script() {
+    let (php, ext) = require_php!();
+    let Some(key) = signing_key() else {
+        eprintln!("skipping: PHP_GUARD_TEST_SIGN_KEY not set");
+        return;
+    };
+    let dir = tempfile::tempdir().unwrap();
+
+    let mut license = License::new("test");
+    license.expires = Date::from_ymd(2000, 1, 1);
+    let license_file = dir.path().join("license.txt");
+    std::fs::write(&license_file, license.issue(&key)).unwrap();
+
+    // 先以明文编译进 OPcache，再换成加密文件。validate_timestamps=0 时 OPcache 直接返回
+    // 缓存的 op array，不会调用编译钩子，相当于授权在常驻进程运行期间到期
+    let protected = dir.path().join("protected.php");
+    std::fs::write(&protected, b"<?php return 'v1';").unwrap();
+    let encrypted = dir.path().join("encrypted.php");
+    std::fs::write(
+        &encrypted,
+        encrypt_content_signed(b"<?php return 'v1';", &key).unwrap(),
+    )
+    .unwrap();
+
+    let main = dir.path().join("main.php");
+    std::fs::write(
+        &main,
+        format!(
+            r#"<?php
+$f = '{}';
+echo include $f, "\n";
+echo opcache_is_script_cached($f) ? "cached" : "not cached", "\n";
+copy('{}', $f);
+try {{
+    echo include $f, "\n";
+}} catch (Exception $e) {{
+    echo $e->getMessage(), "\n";
+}}
+"#,
+            protected.display(),
+            encrypted.display()
+        ),
+    )
+    .unwrap();
+
+    let license_arg = format!("-dphp_guard.license_file={}", license_file.display());
+    let output = run_php(
+        &php,
+        &ext,
+        &[&license_arg, "-dopcache.validate_timestamps=0"],
+        &main,
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "php failed: {:?}", output);
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        ["v1", "cached", "php-guard: license expired on 2000-01-01"]
+    );
+}
@@ -0,0 +1,62 @@
+//! 在真实的 PHP 进程中验证加密文件与 OPcache 的配合。
+//!
+//! 需要本机可用的 `php` (带 opcache) 和已编译的扩展；缺少任一项时跳过，见 `common`。
+
+mod common;
+
+use common::run_php;
+use php_guard_core::encrypt_content;
+
+#[test]
+fn test_encrypted_file_is_cached_and_invalidated() {
+    let (php, ext) = require_php!();
+    let dir = tempfile::tempdir().unwrap();
+
+    let protected = dir.path().join("protected.php");
+    std::fs::write(&protected, encrypt_content(b"<?php return 'v1';").unwrap()).unwrap();
+
+    let main = dir.path().join("main.php");
+    std::fs::write(
+        &main,
+        format!(
+            r#"<?php
+$f = '{}';
+echo include $f, "\n";
+echo opcache_is_script_cached($f) ? "cached" : "not cached", "\n";
+file_put_contents($f, php_guard_encode("<?php return 'v2';"));
+opcache_invalidate($f, true);
+echo include $f, "\n";
+"#,
+            protected.display()
+        ),
+    )
+    .unwrap();
+
+    let output = run_php(&php, &ext, &[], &main);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "php failed: {:?}", output);
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), ["v1", "cached", "v2"]);
+}
+
+#[test]
+fn test_refuses_protected_files_with_file_cache() {
+    let (php, ext) = require_php!();
+    let dir = tempfile::tempdir().unwrap();
+    let file_cache = dir.path().join("file-cache");
+    std::fs::create_dir(&file_cache).unwrap();
+
+    let protected = dir.path().join("protected.php");
+    std::fs::write(
+        &protected,
+        encrypt_content(b"<?php echo 'secret';").unwrap(),
+    )
+    .unwrap();
+
+    let file_cache_arg = format!("-dopcache.file_cache={}", file_cache.display());
+    let output = run_php(&php, &ext, &[&file_cache_arg], &protected);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!stdout.contains("secret"));
+    assert!(format!("{}{}", stdout, stderr).contains("opcache.file_cache"));
+}